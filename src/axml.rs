@@ -0,0 +1,361 @@
+//! android binary xml (e.g `AndroidManifest.xml` in apk) parser,
+//! only elements and attributes are parsed, which is enough to read manifest
+
+use anyhow::{format_err, Context, Result};
+
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_XML_TYPE: u16 = 0x0003;
+const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+const RES_XML_END_ELEMENT_TYPE: u16 = 0x0103;
+const RES_XML_RESOURCE_MAP_TYPE: u16 = 0x0180;
+
+const UTF8_FLAG: u32 = 1 << 8;
+
+const TYPE_REFERENCE: u8 = 0x01;
+const TYPE_STRING: u8 = 0x03;
+const TYPE_INT_DEC: u8 = 0x10;
+const TYPE_INT_HEX: u8 = 0x11;
+const TYPE_INT_BOOLEAN: u8 = 0x12;
+
+/// attribute names in obfuscated manifest may be stripped, resource ids are used instead
+const ATTR_IDS: &[(u32, &str)] = &[
+    (0x0101_0003, "name"),
+    (0x0101_0010, "exported"),
+    (0x0101_020c, "minSdkVersion"),
+    (0x0101_021b, "versionCode"),
+    (0x0101_021c, "versionName"),
+    (0x0101_0270, "targetSdkVersion"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Value {
+    String(String),
+    Int(i64),
+    Bool(bool),
+    Reference(u32),
+    Other(u8, u32),
+}
+
+impl Value {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_string(&self) -> String {
+        match self {
+            Value::String(s) => s.clone(),
+            Value::Int(i) => i.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Reference(r) => format!("@0x{r:08x}"),
+            Value::Other(t, d) => format!("({t:#x})0x{d:08x}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Element {
+    pub name: String,
+    /// (name without namespace, value)
+    pub attrs: Vec<(String, Value)>,
+    pub children: Vec<Element>,
+}
+
+impl Element {
+    pub fn attr(&self, name: &str) -> Option<&Value> {
+        self.attrs.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    pub fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |e| e.name == name)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&self, off: usize) -> Result<u8> {
+        self.data
+            .get(off)
+            .copied()
+            .with_context(|| format!("unexpected eof at {off}"))
+    }
+
+    fn u16(&self, off: usize) -> Result<u16> {
+        let b = self
+            .data
+            .get(off..off + 2)
+            .with_context(|| format!("unexpected eof at {off}"))?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&self, off: usize) -> Result<u32> {
+        let b = self
+            .data
+            .get(off..off + 4)
+            .with_context(|| format!("unexpected eof at {off}"))?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+fn parse_string_pool(r: &Reader, start: usize) -> Result<Vec<String>> {
+    let count = r.u32(start + 8)? as usize;
+    let flags = r.u32(start + 16)?;
+    let strings_start = start + r.u32(start + 20)? as usize;
+    let utf8 = flags & UTF8_FLAG != 0;
+
+    // count is from the file, it's not trusted for allocation
+    let mut strings = Vec::with_capacity(count.min(r.data.len()));
+    for i in 0..count {
+        let mut off = strings_start + r.u32(start + 28 + i * 4)? as usize;
+        let s = if utf8 {
+            // utf16 length, then utf8 length, each is 1 or 2 bytes
+            for _ in 0..2 {
+                let len = r.u8(off)?;
+                off += if len & 0x80 != 0 { 2 } else { 1 };
+            }
+            let data = r
+                .data
+                .get(off..)
+                .with_context(|| format!("unexpected eof at {off}"))?;
+            let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+            String::from_utf8_lossy(&data[..end]).to_string()
+        } else {
+            let mut len = r.u16(off)? as usize;
+            off += 2;
+            if len & 0x8000 != 0 {
+                len = ((len & 0x7fff) << 16) | r.u16(off)? as usize;
+                off += 2;
+            }
+            let units = (0..len)
+                .map(|i| r.u16(off + i * 2))
+                .collect::<Result<Vec<_>>>()?;
+            String::from_utf16_lossy(&units)
+        };
+        strings.push(s);
+    }
+    Ok(strings)
+}
+
+fn string_at(strings: &[String], idx: u32) -> Option<&str> {
+    strings.get(idx as usize).map(String::as_str)
+}
+
+/// parse binary xml and returns the root element
+pub(crate) fn parse(data: &[u8]) -> Result<Element> {
+    let r = Reader { data };
+    if r.u16(0)? != RES_XML_TYPE {
+        return Err(format_err!("not a binary xml"));
+    }
+    let mut strings = vec![];
+    let mut res_ids = vec![];
+    // elements in process, the last one is current
+    let mut stack: Vec<Element> = vec![Element::default()];
+
+    let mut off = r.u16(2)? as usize;
+    while off + 8 <= data.len() {
+        let ty = r.u16(off)?;
+        let header_size = r.u16(off + 2)? as usize;
+        let size = r.u32(off + 4)? as usize;
+        if size < 8 {
+            return Err(format_err!("invalid chunk size {size} at {off}"));
+        }
+        match ty {
+            RES_STRING_POOL_TYPE => strings = parse_string_pool(&r, off)?,
+            RES_XML_RESOURCE_MAP_TYPE => {
+                res_ids = (0..(size - header_size) / 4)
+                    .map(|i| r.u32(off + header_size + i * 4))
+                    .collect::<Result<Vec<_>>>()?;
+            }
+            RES_XML_START_ELEMENT_TYPE => {
+                let ext = off + header_size;
+                let name = string_at(&strings, r.u32(ext + 4)?)
+                    .unwrap_or_default()
+                    .to_string();
+                let attr_start = r.u16(ext + 8)? as usize;
+                let attr_size = r.u16(ext + 10)? as usize;
+                let attr_count = r.u16(ext + 12)? as usize;
+                let mut attrs = vec![];
+                for i in 0..attr_count {
+                    let a = ext + attr_start + i * attr_size;
+                    let name_idx = r.u32(a + 4)?;
+                    let raw = r.u32(a + 8)?;
+                    let data_type = r.u8(a + 15)?;
+                    let data = r.u32(a + 16)?;
+
+                    let mut attr_name = string_at(&strings, name_idx)
+                        .unwrap_or_default()
+                        .to_string();
+                    if let Some(id) = res_ids.get(name_idx as usize) {
+                        if let Some((_, n)) = ATTR_IDS.iter().find(|(i, _)| i == id) {
+                            attr_name = n.to_string();
+                        }
+                    }
+                    let value = match data_type {
+                        TYPE_STRING => {
+                            Value::String(string_at(&strings, data).unwrap_or_default().to_string())
+                        }
+                        TYPE_INT_DEC | TYPE_INT_HEX => Value::Int(data as i32 as i64),
+                        TYPE_INT_BOOLEAN => Value::Bool(data != 0),
+                        TYPE_REFERENCE => Value::Reference(data),
+                        _ => match string_at(&strings, raw) {
+                            Some(s) if raw != u32::MAX => Value::String(s.to_string()),
+                            _ => Value::Other(data_type, data),
+                        },
+                    };
+                    attrs.push((attr_name, value));
+                }
+                stack.push(Element {
+                    name,
+                    attrs,
+                    children: vec![],
+                });
+            }
+            RES_XML_END_ELEMENT_TYPE if stack.len() > 1 => {
+                let e = stack.pop().unwrap();
+                stack.last_mut().unwrap().children.push(e);
+            }
+            _ => {}
+        }
+        off += size;
+    }
+
+    while stack.len() > 1 {
+        let e = stack.pop().unwrap();
+        stack.last_mut().unwrap().children.push(e);
+    }
+    stack
+        .pop()
+        .and_then(|root| root.children.into_iter().next())
+        .context("no element in xml")
+}
+
+#[cfg(test)]
+mod tests {
+    /// <manifest package="com.abc" versionCode=3><uses-sdk minSdkVersion=21/></manifest>
+    fn sample() -> Vec<u8> {
+        fn utf16(s: &str) -> Vec<u8> {
+            let mut v = (s.len() as u16).to_le_bytes().to_vec();
+            s.encode_utf16().for_each(|u| v.extend(u.to_le_bytes()));
+            v.extend([0, 0]);
+            v
+        }
+        let strings = [
+            "manifest",
+            "package",
+            "com.abc",
+            "versionCode",
+            "uses-sdk",
+            "minSdkVersion",
+        ];
+        let mut pool_data = vec![];
+        let mut offsets = vec![];
+        for s in strings {
+            offsets.extend((pool_data.len() as u32).to_le_bytes());
+            pool_data.extend(utf16(s));
+        }
+        while pool_data.len() % 4 != 0 {
+            pool_data.push(0);
+        }
+        let header = 28;
+        let mut pool = vec![];
+        pool.extend(1u16.to_le_bytes());
+        pool.extend((header as u16).to_le_bytes());
+        pool.extend(((header + offsets.len() + pool_data.len()) as u32).to_le_bytes());
+        pool.extend((strings.len() as u32).to_le_bytes());
+        pool.extend(0u32.to_le_bytes());
+        pool.extend(0u32.to_le_bytes());
+        pool.extend(((header + offsets.len()) as u32).to_le_bytes());
+        pool.extend(0u32.to_le_bytes());
+        pool.extend(offsets);
+        pool.extend(pool_data);
+
+        fn attr(name: u32, ty: u8, data: u32, raw: u32) -> Vec<u8> {
+            let mut v = vec![];
+            v.extend(u32::MAX.to_le_bytes());
+            v.extend(name.to_le_bytes());
+            v.extend(raw.to_le_bytes());
+            v.extend(8u16.to_le_bytes());
+            v.push(0);
+            v.push(ty);
+            v.extend(data.to_le_bytes());
+            v
+        }
+        fn start(name: u32, attrs: Vec<Vec<u8>>) -> Vec<u8> {
+            let mut v = vec![];
+            v.extend(0x0102u16.to_le_bytes());
+            v.extend(16u16.to_le_bytes());
+            v.extend(((36 + attrs.len() * 20) as u32).to_le_bytes());
+            v.extend([0u8; 8]);
+            v.extend(u32::MAX.to_le_bytes());
+            v.extend(name.to_le_bytes());
+            v.extend(20u16.to_le_bytes());
+            v.extend(20u16.to_le_bytes());
+            v.extend((attrs.len() as u16).to_le_bytes());
+            v.extend([0u8; 6]);
+            attrs.into_iter().for_each(|a| v.extend(a));
+            v
+        }
+        fn end(name: u32) -> Vec<u8> {
+            let mut v = vec![];
+            v.extend(0x0103u16.to_le_bytes());
+            v.extend(16u16.to_le_bytes());
+            v.extend(24u32.to_le_bytes());
+            v.extend([0u8; 8]);
+            v.extend(u32::MAX.to_le_bytes());
+            v.extend(name.to_le_bytes());
+            v
+        }
+
+        let mut body = pool;
+        body.extend(start(
+            0,
+            vec![attr(1, 0x03, 2, 2), attr(3, 0x10, 3, u32::MAX)],
+        ));
+        body.extend(start(4, vec![attr(5, 0x10, 21, u32::MAX)]));
+        body.extend(end(4));
+        body.extend(end(0));
+
+        let mut xml = vec![];
+        xml.extend(3u16.to_le_bytes());
+        xml.extend(8u16.to_le_bytes());
+        xml.extend(((8 + body.len()) as u32).to_le_bytes());
+        xml.extend(body);
+        xml
+    }
+
+    #[test]
+    fn test_parse() {
+        use super::Value;
+
+        let root = super::parse(&sample()).unwrap();
+        assert_eq!(root.name, "manifest");
+        assert_eq!(
+            root.attr("package"),
+            Some(&Value::String("com.abc".to_string()))
+        );
+        assert_eq!(root.attr("versionCode"), Some(&Value::Int(3)));
+        let sdk = root.children("uses-sdk").next().unwrap();
+        assert_eq!(sdk.attr("minSdkVersion"), Some(&Value::Int(21)));
+    }
+
+    #[test]
+    fn test_string_pool_eof() {
+        // one utf8 string, whose 2-bytes lengths run past the end
+        let mut data = vec![0u8; 8];
+        data.extend(1u32.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend(super::UTF8_FLAG.to_le_bytes());
+        data.extend(32u32.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend([0x80, 0, 0x80]);
+        let r = super::Reader { data: &data };
+        assert!(super::parse_string_pool(&r, 0).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+//! signing certificates of apk. certificates are taken from the apk signing block (v2/v3),
+//! or `META-INF/*.RSA|DSA|EC` (v1, pkcs7) if the apk is not signed by v2/v3
+
+use std::{
+    fs,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+const EOCD_MAGIC: &[u8] = b"PK\x05\x06";
+const EOCD_MIN_SIZE: u64 = 22;
+const SIG_BLOCK_MAGIC: &[u8] = b"APK Sig Block 42";
+const SIG_BLOCK_V2: u32 = 0x7109_871a;
+const SIG_BLOCK_V3: u32 = 0xf053_68c0;
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Certificate {
+    /// v1, v2 or v3
+    pub scheme: &'static str,
+    pub subject: String,
+    pub issuer: String,
+    pub not_before: String,
+    pub not_after: String,
+    /// sha256 of the certificate(der)
+    pub sha256: String,
+}
+
+/// a der element, `raw` includes tag and length
+struct Der<'a> {
+    tag: u8,
+    content: &'a [u8],
+    raw: &'a [u8],
+}
+
+fn read_der(data: &[u8]) -> Option<(Der<'_>, &[u8])> {
+    let tag = *data.first()?;
+    let first = *data.get(1)? as usize;
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let n = first & 0x7f;
+        if n == 0 || n > 4 {
+            return None;
+        }
+        let len = data
+            .get(2..2 + n)?
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        (len, 2 + n)
+    };
+    let end = header.checked_add(len)?;
+    let der = Der {
+        tag,
+        content: data.get(header..end)?,
+        raw: &data[..end],
+    };
+    Some((der, &data[end..]))
+}
+
+fn der_children(data: &[u8]) -> Vec<Der<'_>> {
+    let mut children = vec![];
+    let mut rest = data;
+    while let Some((der, r)) = read_der(rest) {
+        children.push(der);
+        rest = r;
+    }
+    children
+}
+
+/// x.500 name, e.g `CN=Android Debug, O=Android, C=US`
+fn parse_name(name: &[u8]) -> String {
+    let mut parts = vec![];
+    for rdn in der_children(name) {
+        for atv in der_children(rdn.content) {
+            let kv = der_children(atv.content);
+            let (oid, value) = match kv.as_slice() {
+                [oid, value, ..] => (oid.content, value.content),
+                _ => continue,
+            };
+            let key = match oid {
+                [0x55, 0x04, 0x03] => "CN".to_string(),
+                [0x55, 0x04, 0x06] => "C".to_string(),
+                [0x55, 0x04, 0x07] => "L".to_string(),
+                [0x55, 0x04, 0x08] => "ST".to_string(),
+                [0x55, 0x04, 0x0a] => "O".to_string(),
+                [0x55, 0x04, 0x0b] => "OU".to_string(),
+                _ => crate::hash::to_hex(oid),
+            };
+            parts.push(format!("{key}={}", String::from_utf8_lossy(value)));
+        }
+    }
+    parts.join(", ")
+}
+
+fn parse_certificate(scheme: &'static str, der: &[u8]) -> Option<Certificate> {
+    let (cert, _) = read_der(der)?;
+    let (tbs, _) = read_der(cert.content)?;
+    let mut fields = der_children(tbs.content);
+    // version is optional
+    if fields.first().map(|f| f.tag) == Some(0xa0) {
+        fields.remove(0);
+    }
+    // serial, signature algorithm, issuer, validity, subject
+    let issuer = fields.get(2)?;
+    let validity = der_children(fields.get(3)?.content);
+    let subject = fields.get(4)?;
+    let time = |i: usize| {
+        validity
+            .get(i)
+            .map(|t| String::from_utf8_lossy(t.content).to_string())
+            .unwrap_or_default()
+    };
+    Some(Certificate {
+        scheme,
+        subject: parse_name(subject.content),
+        issuer: parse_name(issuer.content),
+        not_before: time(0),
+        not_after: time(1),
+        sha256: crate::hash::sha256(cert.raw),
+    })
+}
+
+/// certificates of a pkcs7 signed data
+fn pkcs7_certificates(data: &[u8]) -> Vec<Certificate> {
+    let certs = (|| {
+        let (content_info, _) = read_der(data)?;
+        let children = der_children(content_info.content);
+        let (signed_data, _) = read_der(children.get(1)?.content)?;
+        let certs = der_children(signed_data.content)
+            .into_iter()
+            .find(|d| d.tag == 0xa0)?;
+        Some(
+            der_children(certs.content)
+                .iter()
+                .filter_map(|c| parse_certificate("v1", c.raw))
+                .collect(),
+        )
+    })();
+    certs.unwrap_or_default()
+}
+
+/// u32 length prefixed data of signing block
+fn length_prefixed(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    let end = 4usize.checked_add(len)?;
+    Some((data.get(4..end)?, &data[end..]))
+}
+
+/// certificates of signers in v2/v3 block
+fn signing_block_certificates(scheme: &'static str, block: &[u8]) -> Vec<Certificate> {
+    let mut result = vec![];
+    let mut signers = match length_prefixed(block) {
+        Some((signers, _)) => signers,
+        None => return result,
+    };
+    while let Some((signer, rest)) = length_prefixed(signers) {
+        signers = rest;
+        let certs = length_prefixed(signer)
+            .and_then(|(signed_data, _)| length_prefixed(signed_data))
+            .and_then(|(_digests, rest)| length_prefixed(rest));
+        let mut certs = match certs {
+            Some((certs, _)) => certs,
+            None => continue,
+        };
+        while let Some((cert, rest)) = length_prefixed(certs) {
+            certs = rest;
+            result.extend(parse_certificate(scheme, cert));
+        }
+    }
+    result
+}
+
+/// find the apk signing block, returns (id, value) pairs
+fn signing_block(apk: &Path) -> Result<Vec<(u32, Vec<u8>)>> {
+    let mut file = fs::File::open(apk).with_context(|| format!("{apk:?} open error"))?;
+    let file_len = file.metadata()?.len();
+    // eocd with the max comment length
+    let tail_len = file_len.min(EOCD_MIN_SIZE + u16::MAX as u64);
+    file.seek(SeekFrom::Start(file_len - tail_len))?;
+    let mut tail = vec![];
+    file.read_to_end(&mut tail)?;
+    let eocd = match tail.windows(4).rposition(|w| w == EOCD_MAGIC) {
+        Some(i) if i + EOCD_MIN_SIZE as usize <= tail.len() => i,
+        _ => return Ok(vec![]),
+    };
+    let cd_offset = u32::from_le_bytes(tail[eocd + 16..eocd + 20].try_into()?) as u64;
+    if cd_offset < 24 {
+        return Ok(vec![]);
+    }
+
+    // block size(u64) and magic are right before central directory
+    let mut footer = [0u8; 24];
+    file.seek(SeekFrom::Start(cd_offset - 24))?;
+    file.read_exact(&mut footer)?;
+    if &footer[8..] != SIG_BLOCK_MAGIC {
+        return Ok(vec![]);
+    }
+    let size = u64::from_le_bytes(footer[..8].try_into()?);
+    // the block starts with its size(u64) too, sizes are from the file and may overflow
+    let pairs_start = match (size.checked_add(8), cd_offset.checked_sub(size)) {
+        (Some(block_size), Some(start)) if size >= 24 && block_size <= cd_offset => start,
+        _ => return Ok(vec![]),
+    };
+    let mut pairs_data = vec![0u8; (size - 24) as usize];
+    file.seek(SeekFrom::Start(pairs_start))?;
+    file.read_exact(&mut pairs_data)?;
+
+    let mut pairs = vec![];
+    let mut rest = pairs_data.as_slice();
+    while rest.len() >= 12 {
+        let len = u64::from_le_bytes(rest[..8].try_into()?);
+        let len = match usize::try_from(len) {
+            Ok(len) if len >= 4 && len <= rest.len() - 8 => len,
+            _ => break,
+        };
+        let id = u32::from_le_bytes(rest[8..12].try_into()?);
+        pairs.push((id, rest[12..8 + len].to_vec()));
+        rest = &rest[8 + len..];
+    }
+    Ok(pairs)
+}
+
+/// v3 is preferred, then v2 and v1
+pub(crate) fn certificates(apk: &Path) -> Result<Vec<Certificate>> {
+    let pairs = signing_block(apk)?;
+    for (id, scheme) in [(SIG_BLOCK_V3, "v3"), (SIG_BLOCK_V2, "v2")] {
+        if let Some((_, block)) = pairs.iter().find(|(i, _)| *i == id) {
+            let certs = signing_block_certificates(scheme, block);
+            if !certs.is_empty() {
+                return Ok(certs);
+            }
+        }
+    }
+
+    let mut certs = vec![];
+    for name in crate::zip::entry_names(apk)? {
+        let upper = name.to_uppercase();
+        if upper.starts_with("META-INF/")
+            && [".RSA", ".DSA", ".EC"].iter().any(|e| upper.ends_with(e))
+        {
+            certs.extend(pkcs7_certificates(&crate::zip::read_entry(apk, &name)?));
+        }
+    }
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_name() {
+        // SEQUENCE { SET { SEQUENCE { OID 2.5.4.3, UTF8String "abc" } } }
+        let name = [
+            0x31, 0x0c, 0x30, 0x0a, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x03, b'a', b'b', b'c',
+        ];
+        assert_eq!(super::parse_name(&name), "CN=abc");
+    }
+
+    #[test]
+    fn test_read_der() {
+        let mut data = vec![0x04, 0x81, 0x80];
+        data.extend([0u8; 0x80]);
+        data.push(0x05);
+        let (der, rest) = super::read_der(&data).unwrap();
+        assert_eq!(der.tag, 0x04);
+        assert_eq!(der.content.len(), 0x80);
+        assert_eq!(rest, &[0x05]);
+    }
+
+    #[test]
+    fn test_signing_block_size_overflow() {
+        // a signing block footer with a huge size, then an eocd pointing after it
+        let mut data = u64::MAX.to_le_bytes().to_vec();
+        data.extend_from_slice(super::SIG_BLOCK_MAGIC);
+        data.extend_from_slice(super::EOCD_MAGIC);
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&24u32.to_le_bytes());
+        data.extend_from_slice(&[0; 2]);
+        let dir = tempfile::tempdir().unwrap();
+        let apk = dir.path().join("a.apk");
+        std::fs::write(&apk, data).unwrap();
+        assert!(super::signing_block(&apk).unwrap().is_empty());
+    }
+}
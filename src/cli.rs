@@ -1,11 +1,12 @@
-use std::process::exit;
+use std::{path::PathBuf, process::exit};
 
 use anyhow::Result;
 use argh::FromArgs;
 
 use crate::{
-    core::{self, RlaConfig},
+    core::{self, Decompiler, InputKind, PackConfig, RlaConfig, RunConfig, ToolOptions},
     deps::{APK_SIGNER, BAKSMALI, SMALI},
+    progress::MessageFormat,
 };
 
 #[derive(FromArgs)]
@@ -16,6 +17,9 @@ struct Cli {
     /// enable verbose mode
     #[argh(switch, short = 'v')]
     verbose: bool,
+    /// human(default), or json to print progress, warnings and errors as json lines
+    #[argh(option, default = "MessageFormat::Human")]
+    message_format: MessageFormat,
 }
 
 #[derive(FromArgs)]
@@ -26,9 +30,20 @@ enum SubCommands {
     Smali(Smali),
     BakSmali(BakSmali),
     Unpack(Unpack),
+    Pull(Pull),
     Pack(Pack),
+    Run(Run),
+    SyncJava(SyncJava),
     JavaToSmali(JavaToSmali),
     SmaliToJava(SmaliToJava),
+    Info(Info),
+    Diff(Diff),
+    Status(Status),
+    History(History),
+    Clean(Clean),
+    Tools(Tools),
+    Cache(Cache),
+    Doctor(Doctor),
 }
 
 #[derive(FromArgs)]
@@ -59,12 +74,15 @@ struct Sign {
 /// init reverse project for the apk file
 #[argh(subcommand, name = "unpack")]
 struct Unpack {
-    /// apk file to unpack
+    /// apk(aar, jar, dex) file to unpack, or a bundle(.apks, .xapk), or a base apk with its split apks
     #[argh(positional)]
-    file: String,
-    /// disable jadx feature
+    files: Vec<String>,
+    /// disable jadx feature(decompile java sources to jadx-src)
     #[argh(switch)]
     no_jadx: bool,
+    /// decompiler of the project: jadx(default), cfr, procyon, vineflower, krakatau
+    #[argh(option, default = "Decompiler::Jadx")]
+    decompiler: Decompiler,
     /// disable git feature
     #[argh(switch)]
     no_git: bool,
@@ -74,6 +92,18 @@ struct Unpack {
     /// force override exists directory
     #[argh(switch)]
     force: bool,
+    /// keep dex which baksmali fails on as they are, instead of failing the unpack
+    #[argh(switch)]
+    keep_going: bool,
+    /// disassemble dex payloads found in apk(e.g in assets), they are put back when packing
+    #[argh(switch)]
+    payloads: bool,
+    /// options of baksmali saved in the project, e.g "--sequential-labels --use-locals"
+    #[argh(option)]
+    baksmali_opts: Option<String>,
+    /// max of tools(e.g baksmali of each dex) running at once, default is cpu count limited by memory
+    #[argh(option, short = 'j')]
+    jobs: Option<usize>,
 }
 
 impl Unpack {
@@ -83,6 +113,100 @@ impl Unpack {
             git_enable: !self.no_git,
             jadx_enable: !self.no_jadx,
             force_override: self.force,
+            decompiler: self.decompiler,
+            splits: vec![],
+            input: InputKind::Apk,
+            payload_enable: self.payloads,
+            payloads: vec![],
+            git_commit_on_pack: false,
+            tools: Default::default(),
+            java_home: None,
+            raw_dex: vec![],
+            keep_going: self.keep_going,
+            tool_options: ToolOptions {
+                baksmali: self
+                    .baksmali_opts
+                    .as_deref()
+                    .map(split_options)
+                    .unwrap_or_default(),
+                smali: vec![],
+            },
+        }
+    }
+}
+
+#[derive(FromArgs)]
+/// pull apks of an installed package by adb, and init reverse project for them
+#[argh(subcommand, name = "pull")]
+struct Pull {
+    /// package name, e.g com.example.app
+    #[argh(positional)]
+    package: String,
+    /// adb binary, default is `$RLA_ADB` or the one in `PATH`
+    #[argh(option)]
+    adb: Option<String>,
+    /// serial of the device
+    #[argh(option, short = 's')]
+    serial: Option<String>,
+    /// disable jadx feature(decompile java sources to jadx-src)
+    #[argh(switch)]
+    no_jadx: bool,
+    /// decompiler of the project: jadx(default), cfr, procyon, vineflower, krakatau
+    #[argh(option, default = "Decompiler::Jadx")]
+    decompiler: Decompiler,
+    /// disable git feature
+    #[argh(switch)]
+    no_git: bool,
+    /// unpack smali only
+    #[argh(switch, long = "smali")]
+    smali_only: bool,
+    /// force override exists directory
+    #[argh(switch)]
+    force: bool,
+    /// keep dex which baksmali fails on as they are, instead of failing the unpack
+    #[argh(switch)]
+    keep_going: bool,
+    /// options of baksmali saved in the project, e.g "--sequential-labels --use-locals"
+    #[argh(option)]
+    baksmali_opts: Option<String>,
+    /// max of tools(e.g baksmali of each dex) running at once, default is cpu count limited by memory
+    #[argh(option, short = 'j')]
+    jobs: Option<usize>,
+}
+
+impl Pull {
+    fn run_config(&self) -> RunConfig {
+        RunConfig {
+            adb: self.adb.as_ref().map(PathBuf::from),
+            serial: self.serial.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn config(&self) -> RlaConfig {
+        RlaConfig {
+            smali_only: self.smali_only,
+            git_enable: !self.no_git,
+            jadx_enable: !self.no_jadx,
+            force_override: self.force,
+            decompiler: self.decompiler,
+            splits: vec![],
+            input: InputKind::Apk,
+            payload_enable: false,
+            payloads: vec![],
+            git_commit_on_pack: false,
+            tools: Default::default(),
+            java_home: None,
+            raw_dex: vec![],
+            keep_going: self.keep_going,
+            tool_options: ToolOptions {
+                baksmali: self
+                    .baksmali_opts
+                    .as_deref()
+                    .map(split_options)
+                    .unwrap_or_default(),
+                smali: vec![],
+            },
         }
     }
 }
@@ -94,24 +218,249 @@ struct Pack {
     /// directory of project
     #[argh(option, short = 'd')]
     dir: Option<String>,
+    /// decompile changed smali to refresh jadx-src after packing
+    #[argh(switch)]
+    refresh_java: bool,
+    /// commit the project with a message naming the output
+    #[argh(switch)]
+    commit: bool,
+    /// rebuild changed dex and sign to output/watch.apk on each change, until Ctrl-C
+    #[argh(switch)]
+    watch: bool,
+    /// install by adb after packing, or after each rebuild in watch mode
+    #[argh(switch)]
+    install: bool,
+    /// adb binary, default is `$RLA_ADB` or the one in `PATH`
+    #[argh(option)]
+    adb: Option<String>,
+    /// serial of the device
+    #[argh(option, short = 's')]
+    serial: Option<String>,
+    /// options of smali instead of the ones in project config, e.g "--verbose"
+    #[argh(option)]
+    smali_opts: Option<String>,
+    /// max of tools(e.g baksmali of each dex) running at once, default is cpu count limited by memory
+    #[argh(option, short = 'j')]
+    jobs: Option<usize>,
+}
+
+impl Pack {
+    fn config(&self) -> PackConfig {
+        let install = if self.install {
+            Some(RunConfig {
+                adb: self.adb.as_ref().map(PathBuf::from),
+                serial: self.serial.clone(),
+                ..Default::default()
+            })
+        } else {
+            None
+        };
+        PackConfig {
+            refresh_java: self.refresh_java,
+            commit: self.commit,
+            watch: self.watch,
+            install,
+            smali_options: self.smali_opts.as_deref().map(split_options),
+        }
+    }
+}
+
+#[derive(FromArgs)]
+/// pack if changed, install the output by adb, start it and show its logcat
+#[argh(subcommand, name = "run")]
+struct Run {
+    /// directory of project
+    #[argh(option, short = 'd')]
+    dir: Option<String>,
+    /// adb binary, default is `$RLA_ADB` or the one in `PATH`
+    #[argh(option)]
+    adb: Option<String>,
+    /// serial of the device
+    #[argh(option, short = 's')]
+    serial: Option<String>,
+    /// uninstall without asking if the installed app is signed by another key
+    #[argh(switch, short = 'y')]
+    yes: bool,
+    /// don't show logcat after starting
+    #[argh(switch)]
+    no_log: bool,
+}
+
+impl Run {
+    fn config(&self) -> RunConfig {
+        RunConfig {
+            adb: self.adb.as_ref().map(PathBuf::from),
+            serial: self.serial.clone(),
+            yes: self.yes,
+            no_log: self.no_log,
+        }
+    }
+}
+
+#[derive(FromArgs)]
+/// decompile changed smali to refresh jadx-src
+#[argh(subcommand, name = "sync-java")]
+struct SyncJava {
+    /// directory of project
+    #[argh(option, short = 'd')]
+    dir: Option<String>,
+}
+
+#[derive(FromArgs)]
+/// show changed smali and unpacked files since unpack, and since the last pack
+#[argh(subcommand, name = "status")]
+struct Status {
+    /// directory of project
+    #[argh(option, short = 'd')]
+    dir: Option<String>,
+    /// print as json
+    #[argh(switch)]
+    json: bool,
+}
+
+#[derive(FromArgs)]
+/// list outputs with their build metadata
+#[argh(subcommand, name = "history")]
+struct History {
+    /// directory of project
+    #[argh(option, short = 'd')]
+    dir: Option<String>,
+    #[argh(subcommand)]
+    show: Option<HistoryShow>,
+}
+
+#[derive(FromArgs)]
+/// show build metadata of an output
+#[argh(subcommand, name = "show")]
+struct HistoryShow {
+    /// number of the output, e.g 3 for output/3.apk
+    #[argh(positional)]
+    n: usize,
+}
+
+#[derive(FromArgs)]
+/// remove old outputs
+#[argh(subcommand, name = "clean")]
+struct Clean {
+    /// directory of project
+    #[argh(option, short = 'd')]
+    dir: Option<String>,
+    /// number of latest outputs to keep
+    #[argh(option, default = "5")]
+    keep: usize,
+}
+
+#[derive(FromArgs)]
+/// tools used by rla, e.g smali, apksigner
+#[argh(subcommand, name = "tools")]
+struct Tools {
+    #[argh(subcommand)]
+    nested: ToolsCommands,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum ToolsCommands {
+    List(ToolsList),
+}
+
+#[derive(FromArgs)]
+/// list tools with their sources and versions, and check that they run
+#[argh(subcommand, name = "list")]
+struct ToolsList {
+    /// directory of project, whose config may give local tools
+    #[argh(option, short = 'd')]
+    dir: Option<String>,
 }
 
 #[derive(FromArgs)]
-/// compile java (to smali)
+/// cache of embedded tools, at `$XDG_CACHE_HOME/rla`
+#[argh(subcommand, name = "cache")]
+struct Cache {
+    #[argh(subcommand)]
+    nested: CacheCommands,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum CacheCommands {
+    Clean(CacheClean),
+}
+
+#[derive(FromArgs)]
+/// remove the cache, tools are extracted again when used
+#[argh(subcommand, name = "clean")]
+struct CacheClean {}
+
+#[derive(FromArgs)]
+/// check java, javac, jadx, git, adb and zip used by rla
+#[argh(subcommand, name = "doctor")]
+struct Doctor {
+    /// directory of project, whose config may give the java home
+    #[argh(option, short = 'd')]
+    dir: Option<String>,
+}
+
+#[derive(FromArgs)]
+/// compile java or kotlin (to smali)
 #[argh(subcommand, name = "cj")]
 struct JavaToSmali {
-    /// either a java file or a root dir for java files
+    /// either a java/kotlin file or a root dir for java/kotlin files
     #[argh(positional)]
     path: String,
+    /// bundle kotlin stdlib classes used by the code into smali, instead of referencing the ones in app
+    #[argh(switch)]
+    kotlin_stdlib: bool,
 }
 
 #[derive(FromArgs)]
 /// compile smali (to java)
 #[argh(subcommand, name = "cs")]
 struct SmaliToJava {
-    /// a smali file to compile
+    /// a smali file or a directory of smali files to compile
     #[argh(positional)]
     path: String,
+    /// only compile classes matched, `*` matches any characters, e.g "com.abc.*"
+    #[argh(option, long = "class")]
+    class_pattern: Option<String>,
+    /// write java files to this directory with package layout, instead of next to smali files
+    #[argh(option, short = 'o')]
+    out: Option<String>,
+    /// decompiler to use, default is the one of project or jadx
+    #[argh(option)]
+    decompiler: Option<Decompiler>,
+}
+
+#[derive(FromArgs)]
+/// show package, sdk, permissions, components, abis, dex and signing info
+#[argh(subcommand, name = "info")]
+struct Info {
+    /// apk file or project directory, default is the current project
+    #[argh(positional)]
+    path: Option<String>,
+    /// print as json
+    #[argh(switch)]
+    json: bool,
+}
+
+#[derive(FromArgs)]
+/// compare two apk(aar, jar, dex) files by entries, and by classes and methods for dex
+#[argh(subcommand, name = "diff")]
+struct Diff {
+    /// the old file, e.g bak.apk
+    #[argh(positional)]
+    a: String,
+    /// the new file, e.g output/1.apk
+    #[argh(positional)]
+    b: String,
+    /// print as json
+    #[argh(switch)]
+    json: bool,
+}
+
+/// "--use-locals --debug-info false" => ["--use-locals", "--debug-info", "false"]
+fn split_options(options: &str) -> Vec<String> {
+    options.split_whitespace().map(str::to_string).collect()
 }
 
 // `argh` doesn't support forward all arguments to another command,
@@ -138,13 +487,46 @@ pub(crate) fn run() -> Result<()> {
     }
 
     let cli: Cli = argh::from_env();
-    crate::log::init_logger(cli.verbose);
+    crate::progress::set_format(cli.message_format);
+    crate::log::init_logger(cli.verbose, cli.message_format == MessageFormat::Json);
     match cli.nested {
         SubCommands::Sign(Sign { file }) => crate::cmd::debugsign(file.as_ref()),
-        SubCommands::Unpack(c) => core::unpack_apk(&c.file, c.config()),
-        SubCommands::Pack(Pack { dir }) => core::pack_apk(dir),
-        SubCommands::JavaToSmali(JavaToSmali { path }) => core::java_to_smali(&path),
-        SubCommands::SmaliToJava(SmaliToJava { path }) => core::smali_to_java(&path),
+        SubCommands::Unpack(c) => {
+            crate::runtime::set_jobs(c.jobs);
+            core::unpack_apk(&c.files, c.config())
+        }
+        SubCommands::Pull(c) => {
+            crate::runtime::set_jobs(c.jobs);
+            core::pull_apk(&c.package, c.run_config(), c.config())
+        }
+        SubCommands::Pack(c) => {
+            crate::runtime::set_jobs(c.jobs);
+            core::pack_apk(c.dir.clone(), c.config())
+        }
+        SubCommands::Run(c) => core::run_apk(c.dir.clone(), c.config()),
+        SubCommands::SyncJava(SyncJava { dir }) => core::sync_java(dir),
+        SubCommands::Status(Status { dir, json }) => core::status(dir, json),
+        SubCommands::History(History { dir, show }) => core::history(dir, show.map(|s| s.n)),
+        SubCommands::Clean(Clean { dir, keep }) => core::clean(dir, keep),
+        SubCommands::Tools(Tools {
+            nested: ToolsCommands::List(ToolsList { dir }),
+        }) => core::tools_list(dir),
+        SubCommands::Cache(Cache {
+            nested: CacheCommands::Clean(_),
+        }) => crate::deps::clean_cache(),
+        SubCommands::Doctor(Doctor { dir }) => core::doctor(dir),
+        SubCommands::JavaToSmali(JavaToSmali {
+            path,
+            kotlin_stdlib,
+        }) => core::java_to_smali(&path, kotlin_stdlib),
+        SubCommands::SmaliToJava(SmaliToJava {
+            path,
+            class_pattern,
+            out,
+            decompiler,
+        }) => core::smali_to_java(&path, class_pattern.as_deref(), out.as_deref(), decompiler),
+        SubCommands::Info(Info { path, json }) => core::info(path, json),
+        SubCommands::Diff(Diff { a, b, json }) => core::diff(&a, &b, json),
         _ => {
             eprintln!("unhandled command, internal bug!");
             exit(-1);
@@ -0,0 +1,218 @@
+//! adb wrapper. the binary is `--adb`, `$RLA_ADB`, `$ANDROID_HOME/platform-tools/adb` or `adb` in `PATH`,
+//! so it can be replaced by a fake script in tests
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{format_err, Context, Result};
+
+/// messages of `adb install` when the installed app is signed by another key
+const SIGNATURE_MISMATCH: &[&str] = &[
+    "INSTALL_FAILED_UPDATE_INCOMPATIBLE",
+    "INSTALL_FAILED_SHARED_USER_INCOMPATIBLE",
+    "signatures do not match",
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Install {
+    Success,
+    /// uninstall the app to install it
+    SignatureMismatch(String),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Adb {
+    bin: PathBuf,
+    /// device serial, passed as `adb -s`
+    serial: Option<String>,
+}
+
+fn find_adb() -> Result<PathBuf> {
+    if let Some(adb) = std::env::var_os("RLA_ADB") {
+        return Ok(PathBuf::from(adb));
+    }
+    if let Some(home) = std::env::var_os("ANDROID_HOME") {
+        let adb = Path::new(&home).join("platform-tools").join("adb");
+        if adb.exists() {
+            return Ok(adb);
+        }
+    }
+    super::which("adb").context("adb not found, set it by `--adb` or `RLA_ADB`")
+}
+
+impl Adb {
+    pub fn new(bin: Option<PathBuf>, serial: Option<String>) -> Result<Self> {
+        let bin = match bin {
+            Some(bin) => bin,
+            None => find_adb()?,
+        };
+        Ok(Self { bin, serial })
+    }
+
+    fn command<I, S>(&self, args: I) -> Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut c = Command::new(&self.bin);
+        if let Some(serial) = &self.serial {
+            c.arg("-s").arg(serial);
+        }
+        c.args(args);
+        c
+    }
+
+    pub fn run<I, S>(&self, args: I) -> Result<String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        super::run(self.command(args))
+    }
+
+    pub fn bin(&self) -> &Path {
+        &self.bin
+    }
+
+    /// first line of `adb version`
+    pub fn version(&self) -> Result<String> {
+        let out = self.run(["version"])?;
+        Ok(out.lines().next().unwrap_or_default().to_string())
+    }
+
+    /// paths of the base apk and split apks of an installed package
+    pub fn package_paths(&self, package: &str) -> Result<Vec<String>> {
+        let out = self.run(["shell", "pm", "path", package])?;
+        let paths = out
+            .lines()
+            .filter_map(|l| l.trim().strip_prefix("package:"))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        if paths.is_empty() {
+            return Err(format_err!("package {package} not found on the device"));
+        }
+        Ok(paths)
+    }
+
+    pub fn pull(&self, remote: &str, local: &Path) -> Result<()> {
+        self.run([OsStr::new("pull"), OsStr::new(remote), local.as_os_str()])
+            .map(|_| ())
+    }
+
+    /// `install-multiple` is used for split apks
+    pub fn install(&self, apks: &[PathBuf]) -> Result<Install> {
+        let mut args = vec![
+            if apks.len() > 1 {
+                "install-multiple"
+            } else {
+                "install"
+            }
+            .into(),
+            "-r".into(),
+        ];
+        args.extend(apks.iter().map(|p| p.as_os_str().to_os_string()));
+        // old adb exits with 0 on failure
+        let msg = match self.run(&args) {
+            Ok(msg) if !msg.contains("Failure") => return Ok(Install::Success),
+            Ok(msg) => msg,
+            Err(e) => format!("{e:?}"),
+        };
+        if SIGNATURE_MISMATCH.iter().any(|m| msg.contains(m)) {
+            Ok(Install::SignatureMismatch(msg))
+        } else {
+            Err(format_err!("install {apks:?} failed: {msg}"))
+        }
+    }
+
+    pub fn uninstall(&self, package: &str) -> Result<()> {
+        self.run(["uninstall", package]).map(|_| ())
+    }
+
+    pub fn start(&self, package: &str, activity: &str) -> Result<()> {
+        let component = format!("{package}/{activity}");
+        let msg = self.run(["shell", "am", "start", "-n", &component])?;
+        if msg.contains("Error") {
+            return Err(format_err!("start {component} failed: {msg}"));
+        }
+        Ok(())
+    }
+
+    /// wait for the process of package, returns its pid
+    pub fn wait_pid(&self, package: &str, timeout: Duration) -> Result<u32> {
+        let interval = Duration::from_millis(200);
+        let mut waited = Duration::ZERO;
+        loop {
+            let out = self.run(["shell", "pidof", package]).unwrap_or_default();
+            if let Some(pid) = out.split_whitespace().find_map(|p| p.parse().ok()) {
+                return Ok(pid);
+            }
+            if waited >= timeout {
+                return Err(format_err!("process of {package} not found"));
+            }
+            thread::sleep(interval);
+            waited += interval;
+        }
+    }
+
+    /// stream logcat of the process until it's interrupted
+    pub fn logcat(&self, pid: u32) -> Result<()> {
+        super::run_interit(self.command(["logcat".to_string(), format!("--pid={pid}")]))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, path::PathBuf};
+
+    use super::{Adb, Install};
+
+    fn fake_adb(script: &str) -> (tempfile::TempDir, Adb) {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("adb");
+        std::fs::write(&bin, format!("#!/bin/sh\n{script}\n")).unwrap();
+        std::fs::set_permissions(&bin, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let adb = Adb::new(Some(bin), Some("emulator-5554".to_string())).unwrap();
+        (dir, adb)
+    }
+
+    #[test]
+    fn test_install() {
+        let (_dir, adb) = fake_adb(
+            r#"[ "$3" = "install" ] && echo "Failure [INSTALL_FAILED_UPDATE_INCOMPATIBLE: x]" && exit 1"#,
+        );
+        let result = adb.install(&[PathBuf::from("1.apk")]).unwrap();
+        assert!(matches!(result, Install::SignatureMismatch(_)));
+
+        let (_dir, adb) = fake_adb("echo Success");
+        let result = adb.install(&[PathBuf::from("1.apk")]).unwrap();
+        assert_eq!(result, Install::Success);
+    }
+
+    #[test]
+    fn test_package_paths() {
+        let (_dir, adb) = fake_adb(
+            r#"echo "package:/data/app/com.abc-1/base.apk"
+echo "package:/data/app/com.abc-1/split_config.en.apk""#,
+        );
+        let paths = adb.package_paths("com.abc").unwrap();
+        assert_eq!(
+            paths,
+            [
+                "/data/app/com.abc-1/base.apk",
+                "/data/app/com.abc-1/split_config.en.apk"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wait_pid() {
+        let (_dir, adb) = fake_adb(r#"[ "$4" = "pidof" ] && echo 1234"#);
+        let pid = adb.wait_pid("com.abc", std::time::Duration::ZERO).unwrap();
+        assert_eq!(pid, 1234);
+    }
+}
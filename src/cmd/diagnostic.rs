@@ -0,0 +1,467 @@
+//! errors of smali, javac, dx(or d8) and apksigner parsed from their output,
+//! they are rendered like compiler errors with the source line
+
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+use tracing::debug;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Tool {
+    Smali,
+    Javac,
+    Dx,
+    Apksigner,
+}
+
+impl Display for Tool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Tool::Smali => "smali",
+            Tool::Javac => "javac",
+            Tool::Dx => "dx",
+            Tool::Apksigner => "apksigner",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Diagnostic {
+    pub severity: Severity,
+    pub file: Option<PathBuf>,
+    /// 1-based
+    pub line: Option<usize>,
+    /// 1-based
+    pub column: Option<usize>,
+    pub message: String,
+    /// extra lines, e.g `symbol: method foo()` of javac
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, message: &str) -> Self {
+        Self {
+            severity,
+            file: None,
+            line: None,
+            column: None,
+            message: message.trim().to_string(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// the line of the file, with the column marked
+    fn snippet(&self) -> Option<(String, Option<String>)> {
+        let text = fs::read_to_string(self.file.as_ref()?).ok()?;
+        let line = text.lines().nth(self.line?.checked_sub(1)?)?.to_string();
+        let marker = self.column.map(|c| {
+            // keep tabs to line up with the source
+            let pad = line
+                .chars()
+                .take(c.saturating_sub(1))
+                .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+                .collect::<String>();
+            format!("{pad}^")
+        });
+        Some((line, marker))
+    }
+}
+
+impl Diagnostic {
+    /// e.g `error[smali]: msg`, then the location and the source line
+    fn render(&self, f: &mut fmt::Formatter<'_>, tool: Tool) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{severity}[{tool}]: {}", self.message)?;
+        let width = self.line.map(|l| l.to_string().len()).unwrap_or(1);
+        let gutter = " ".repeat(width);
+        if let Some(file) = &self.file {
+            write!(f, "\n{gutter}--> {}", file.display())?;
+            if let Some(line) = self.line {
+                write!(f, ":{line}")?;
+                if let Some(column) = self.column {
+                    write!(f, ":{column}")?;
+                }
+            }
+        }
+        let snippet = self.snippet();
+        if let (Some((source, marker)), Some(line)) = (&snippet, self.line) {
+            write!(f, "\n{gutter} |\n{line} | {source}")?;
+            if let Some(marker) = marker {
+                write!(f, "\n{gutter} | {marker}")?;
+            }
+        }
+        for note in &self.notes {
+            write!(f, "\n{gutter} = {note}")?;
+        }
+        Ok(())
+    }
+}
+
+/// a tool failed with diagnostics, the output is logged at debug level
+#[derive(Debug)]
+pub(crate) struct ToolError {
+    pub tool: Tool,
+    pub diagnostics: Vec<Diagnostic>,
+    /// diagnostics are emitted as json events once
+    reported: AtomicBool,
+}
+
+impl ToolError {
+    /// `None` if the output has no known diagnostic.
+    /// relative paths are resolved against `base`, which is the signed apk for apksigner
+    pub(crate) fn parse(tool: Tool, output: &str, base: &Path) -> Option<Self> {
+        let diagnostics = match tool {
+            Tool::Smali => parse_smali(output, base),
+            Tool::Javac => parse_javac(output, base),
+            Tool::Dx => parse_dx(output, base),
+            Tool::Apksigner => parse_apksigner(output, base),
+        };
+        if diagnostics.is_empty() {
+            return None;
+        }
+        Some(Self {
+            tool,
+            diagnostics,
+            reported: AtomicBool::new(false),
+        })
+    }
+
+    /// true for the first call only
+    pub(crate) fn report(&self) -> bool {
+        !self.reported.swap(true, Ordering::Relaxed)
+    }
+}
+
+impl Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, d) in self.diagnostics.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n\n")?;
+            }
+            d.render(f, self.tool)?;
+        }
+        let errors = self
+            .diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .count();
+        if errors > 1 {
+            write!(f, "\n\n{} failed with {errors} errors", self.tool)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ToolError {}
+
+/// turn the error of a tool into a `ToolError` if its output is known
+pub(crate) fn diagnose<T>(result: Result<T>, tool: Tool, base: &Path) -> Result<T> {
+    result.map_err(|e| {
+        let output = e.to_string();
+        match ToolError::parse(tool, &output, base) {
+            Some(tool_error) => {
+                debug!("{output}");
+                tool_error.into()
+            }
+            None => e,
+        }
+    })
+}
+
+fn resolve(base: &Path, file: &str) -> PathBuf {
+    let path = Path::new(file.trim());
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        base.join(path)
+    }
+}
+
+/// `smalis/classes.dex/a/A.smali[11,0] missing EOF at 'x'`, the column is 0-based
+fn parse_smali(output: &str, base: &Path) -> Vec<Diagnostic> {
+    let parse = |l: &str| {
+        let (file, rest) = l.split_once(".smali[")?;
+        let (pos, message) = rest.split_once("] ")?;
+        let (line, column) = pos.split_once(',')?;
+        let mut d = Diagnostic::new(Severity::Error, message);
+        d.file = Some(resolve(base, &format!("{file}.smali")));
+        d.line = Some(line.parse().ok()?);
+        d.column = Some(column.parse::<usize>().ok()? + 1);
+        Some(d)
+    };
+    output.lines().filter_map(parse).collect()
+}
+
+/// ```text
+/// a/A.java:4: error: cannot find symbol
+///     void f() { undefinedCall(); }
+///                ^
+///   symbol:   method undefinedCall()
+/// ```
+fn parse_javac(output: &str, base: &Path) -> Vec<Diagnostic> {
+    let header = |l: &str| {
+        let (file, rest) = l.split_once(".java:")?;
+        let (line, rest) = rest.split_once(": ")?;
+        let (severity, message) = severity_of(rest)?;
+        let mut d = Diagnostic::new(severity, message);
+        d.file = Some(resolve(base, &format!("{file}.java")));
+        d.line = Some(line.parse().ok()?);
+        Some(d)
+    };
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    // lines after a header: the source, the marker, then notes
+    let mut after = 0;
+    for l in output.lines() {
+        if let Some(d) = header(l) {
+            diagnostics.push(d);
+            after = 1;
+            continue;
+        }
+        if let Some((severity, message)) = severity_of(l) {
+            // e.g `error: file not found: A.java`
+            diagnostics.push(Diagnostic::new(severity, message));
+            after = 0;
+            continue;
+        }
+        let last = match diagnostics.last_mut() {
+            Some(last) if after > 0 => last,
+            _ => continue,
+        };
+        after += 1;
+        match after {
+            2 => {}
+            3 if l.trim() == "^" => last.column = l.find('^').map(|c| c + 1),
+            _ if l.starts_with(' ') && !l.trim().is_empty() => {
+                last.notes.push(l.trim().to_string())
+            }
+            _ => after = 0,
+        }
+    }
+    diagnostics
+}
+
+fn severity_of(l: &str) -> Option<(Severity, &str)> {
+    if let Some(message) = l.strip_prefix("error: ") {
+        Some((Severity::Error, message))
+    } else {
+        l.strip_prefix("warning: ")
+            .map(|message| (Severity::Warning, message))
+    }
+}
+
+/// ```text
+/// PARSE ERROR:
+/// unsupported class file version 55.0
+/// ...while parsing a/B.class
+/// ```
+/// and `Error in a/B.class:` of d8
+fn parse_dx(output: &str, base: &Path) -> Vec<Diagnostic> {
+    const HEADERS: [&str; 4] = [
+        "PARSE ERROR:",
+        "UNEXPECTED TOP-LEVEL EXCEPTION:",
+        "UNEXPECTED TOP-LEVEL ERROR:",
+        "trouble processing",
+    ];
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut open = false;
+    for l in output.lines() {
+        let t = l.trim();
+        if HEADERS.iter().any(|h| t.starts_with(h)) {
+            let mut d = Diagnostic::new(Severity::Error, "");
+            // trouble processing "a/B.class":
+            if let Some(file) = t.split('"').nth(1) {
+                d.file = Some(resolve(base, file));
+            }
+            diagnostics.push(d);
+            open = true;
+            continue;
+        }
+        if let Some(rest) = t
+            .strip_prefix("Error in ")
+            .or_else(|| t.strip_prefix("Warning in "))
+        {
+            let severity = if t.starts_with("Error") {
+                Severity::Error
+            } else {
+                Severity::Warning
+            };
+            let rest = rest.trim_end_matches(':');
+            let file = rest.split(" at ").next().unwrap_or(rest);
+            let mut d = Diagnostic::new(severity, "");
+            d.file = Some(resolve(base, file));
+            diagnostics.push(d);
+            open = true;
+            continue;
+        }
+        let last = match diagnostics.last_mut() {
+            Some(last) if open => last,
+            _ => continue,
+        };
+        if t.is_empty() || t.ends_with("error; aborting") || t.ends_with("errors; aborting") {
+            open = false;
+        } else if let Some(file) = t.strip_prefix("...while parsing ") {
+            last.file.get_or_insert_with(|| resolve(base, file));
+        } else if t.starts_with("at ") || t.starts_with("...") {
+            // stack frames and methods being processed
+            if t.starts_with("...while") {
+                last.notes.push(t.trim_start_matches('.').to_string());
+            }
+        } else if last.message.is_empty() {
+            last.message = t.to_string();
+        } else {
+            last.notes.push(t.to_string());
+        }
+    }
+    // dx catches the exit trapped by the jvm worker and prints it as an unexpected exception
+    diagnostics.retain(|d| !d.message.is_empty() && !d.message.starts_with("RlaWorker$Exit"));
+    diagnostics
+}
+
+/// uncaught exceptions, e.g `ApkFormatException: Malformed APK: not a ZIP archive`
+fn parse_apksigner(output: &str, apk: &Path) -> Vec<Diagnostic> {
+    // `java.io.IOException: msg`, the message of an exception without one is its name
+    let exception = |l: &str| {
+        let l = l.trim_start_matches("Exception in thread \"main\" ");
+        let (class, message) = l.split_once(": ").unwrap_or((l, ""));
+        let name = class.rsplit('.').next()?;
+        let is_exception = class.contains('.')
+            && !class.contains(' ')
+            && (name.ends_with("Exception") || name.ends_with("Error"));
+        is_exception.then(|| {
+            if message.is_empty() {
+                name.to_string()
+            } else {
+                message.to_string()
+            }
+        })
+    };
+    let mut diagnostic: Option<Diagnostic> = None;
+    for l in output.lines() {
+        let t = l.trim();
+        let message = t
+            .strip_prefix("ERROR: ")
+            .map(ToString::to_string)
+            .or_else(|| t.starts_with("Failed to ").then(|| t.to_string()))
+            .or_else(|| exception(t));
+        if let Some(d) = &mut diagnostic {
+            if let Some(cause) = t.strip_prefix("Caused by: ").and_then(exception) {
+                d.notes.push(format!("caused by: {cause}"));
+            } else if let Some(message) = message.filter(|_| d.message.starts_with("Failed to ")) {
+                // `Failed to load signer` is followed by the exception
+                d.notes.push(message);
+            }
+            continue;
+        }
+        if let Some(message) = message {
+            let mut d = Diagnostic::new(Severity::Error, &message);
+            d.file = Some(apk.to_path_buf());
+            diagnostic = Some(d);
+        }
+    }
+    diagnostic.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_smali() {
+        let output = "smalis/classes.dex/a/A.smali[11,0] missing EOF at 'garbage'\n";
+        let d = &parse_smali(output, Path::new("/p"))[0];
+        assert_eq!(
+            d.file.as_deref(),
+            Some(Path::new("/p/smalis/classes.dex/a/A.smali"))
+        );
+        assert_eq!((d.line, d.column), (Some(11), Some(1)));
+        assert_eq!(d.message, "missing EOF at 'garbage'");
+    }
+
+    #[test]
+    fn test_parse_javac() {
+        let output = r#"a/A.java:4: error: cannot find symbol
+    void f() { undefinedCall(); }
+               ^
+  symbol:   method undefinedCall()
+  location: class A
+a/A.java:5: error: incompatible types: String cannot be converted to int
+    void g() { int y = "s"; }
+                       ^
+2 errors
+"#;
+        let ds = parse_javac(output, Path::new("/src"));
+        assert_eq!(ds.len(), 2);
+        assert_eq!(ds[0].file.as_deref(), Some(Path::new("/src/a/A.java")));
+        assert_eq!((ds[0].line, ds[0].column), (Some(4), Some(16)));
+        assert_eq!(ds[0].message, "cannot find symbol");
+        assert_eq!(
+            ds[0].notes,
+            ["symbol:   method undefinedCall()", "location: class A"]
+        );
+        assert_eq!((ds[1].line, ds[1].column), (Some(5), Some(24)));
+        assert!(ds[1].notes.is_empty());
+    }
+
+    #[test]
+    fn test_parse_dx() {
+        let output = "\nPARSE ERROR:\nunsupported class file version 55.0\n\
+                      ...while parsing a/B.class\n1 error; aborting\n";
+        let ds = parse_dx(output, Path::new("/src"));
+        assert_eq!(ds.len(), 1);
+        assert_eq!(ds[0].message, "unsupported class file version 55.0");
+        assert_eq!(ds[0].file.as_deref(), Some(Path::new("/src/a/B.class")));
+
+        let output =
+            "Error in /src/a/B.class:\nInvoke-customs are only supported starting with Android O\n";
+        let ds = parse_dx(output, Path::new("/"));
+        assert_eq!(ds[0].file.as_deref(), Some(Path::new("/src/a/B.class")));
+        assert_eq!(
+            ds[0].message,
+            "Invoke-customs are only supported starting with Android O"
+        );
+
+        let output = "\nPARSE ERROR:\nunsupported class file version 55.0\n\
+                      ...while parsing a/B.class\n1 error; aborting\n\n\
+                      UNEXPECTED TOP-LEVEL EXCEPTION:\nRlaWorker$Exit: exit 1\n\
+                      \tat RlaWorker$1.checkExit(RlaWorker.java:100)\n";
+        let ds = parse_dx(output, Path::new("/src"));
+        assert_eq!(ds.len(), 1);
+        assert_eq!(ds[0].message, "unsupported class file version 55.0");
+    }
+
+    #[test]
+    fn test_parse_apksigner() {
+        let output = "Exception in thread \"main\" com.android.apksig.apk.ApkFormatException: \
+                      Malformed APK: not a ZIP archive\n\
+                      \tat com.android.apksig.ApkSigner.sign(ApkSigner.java:235)\n\
+                      Caused by: com.android.apksig.zip.ZipFormatException: \
+                      ZIP End of Central Directory record not found\n";
+        let ds = parse_apksigner(output, Path::new("a.apk"));
+        assert_eq!(ds.len(), 1);
+        assert_eq!(ds[0].message, "Malformed APK: not a ZIP archive");
+        assert_eq!(ds[0].file.as_deref(), Some(Path::new("a.apk")));
+        assert_eq!(
+            ds[0].notes,
+            ["caused by: ZIP End of Central Directory record not found"]
+        );
+        assert!(parse_apksigner("Signed\n", Path::new("a.apk")).is_empty());
+    }
+}
@@ -0,0 +1,231 @@
+//! java runtime used by tools. it's `java_home` of the project config, `$JAVA_HOME`, or `java` in `PATH`
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    sync::RwLock,
+};
+
+use anyhow::{format_err, Context, Result};
+use once_cell::sync::Lazy;
+use tracing::debug;
+
+/// jars of smali, baksmali, apksigner and dx
+pub(crate) const MIN_JAVA: u32 = 8;
+/// `javac --release 8`, `--release` is added in java 9
+pub(crate) const MIN_JAVAC: u32 = 9;
+/// d8 and jadx 1.4+
+pub(crate) const MIN_D8: u32 = 11;
+
+/// `java_home` of the project config, relative to the project root
+static HOME_OVERRIDE: Lazy<RwLock<Option<PathBuf>>> = Lazy::new(Default::default);
+/// the runtime found, it's reset when the override changes
+static RUNTIME: Lazy<RwLock<Option<Runtime>>> = Lazy::new(Default::default);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum JavaSource {
+    Config,
+    JavaHome,
+    Path,
+}
+
+impl std::fmt::Display for JavaSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JavaSource::Config => write!(f, "java_home of {}", crate::core::RLA_CONFIG),
+            JavaSource::JavaHome => write!(f, "JAVA_HOME"),
+            JavaSource::Path => write!(f, "PATH"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Runtime {
+    pub java: PathBuf,
+    /// the jdk(or jre) directory containing `bin/java`
+    pub home: Option<PathBuf>,
+    pub source: JavaSource,
+    /// first line of `java -version`, e.g `openjdk version "11.0.2" 2019-01-15`
+    pub version: String,
+    /// e.g 8 for `1.8.0_301`, 17 for `17.0.2`
+    pub major: u32,
+}
+
+/// set by the project config, `None` to use `$JAVA_HOME` or `PATH`
+pub(crate) fn set_java_home(home: Option<PathBuf>) {
+    if let Ok(mut current) = HOME_OVERRIDE.write() {
+        if *current != home {
+            *current = home;
+            if let Ok(mut runtime) = RUNTIME.write() {
+                *runtime = None;
+            }
+        }
+    }
+}
+
+fn bin(home: &Path, name: &str) -> PathBuf {
+    home.join("bin")
+        .join(format!("{name}{}", std::env::consts::EXE_SUFFIX))
+}
+
+fn locate() -> Result<(PathBuf, Option<PathBuf>, JavaSource)> {
+    let configured = HOME_OVERRIDE.read().ok().and_then(|h| h.clone());
+    if let Some(home) = configured {
+        let java = bin(&home, "java");
+        if !java.is_file() {
+            return Err(format_err!(
+                "{java:?} not found, fix `java_home` of {}",
+                crate::core::RLA_CONFIG
+            ));
+        }
+        return Ok((java, Some(home), JavaSource::Config));
+    }
+    if let Some(home) = std::env::var_os("JAVA_HOME").filter(|h| !h.is_empty()) {
+        let home = PathBuf::from(home);
+        let java = bin(&home, "java");
+        if !java.is_file() {
+            return Err(format_err!(
+                "{java:?} not found, fix JAVA_HOME or unset it to use java in PATH"
+            ));
+        }
+        return Ok((java, Some(home), JavaSource::JavaHome));
+    }
+    let java = super::which("java").map_err(|_| {
+        format_err!(
+            "java not found, install a jdk {MIN_D8}+ and set JAVA_HOME(or `java_home` of {}), \
+             or add java to PATH",
+            crate::core::RLA_CONFIG
+        )
+    })?;
+    // e.g /usr/bin/java -> /usr/lib/jvm/java-17-openjdk/bin/java
+    let home = java
+        .canonicalize()
+        .ok()
+        .and_then(|p| Some(p.parent()?.parent()?.to_path_buf()));
+    Ok((java, home, JavaSource::Path))
+}
+
+/// the major version in the quoted version, `1.x` is java x
+pub(crate) fn parse_major(version: &str) -> Option<u32> {
+    let quoted = version.split('"').nth(1)?;
+    let mut parts = quoted.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    if major == 1 {
+        return parts.next()?.parse().ok();
+    }
+    Some(major)
+}
+
+fn probe() -> Result<Runtime> {
+    let (java, home, source) = locate()?;
+    let mut c = Command::new(&java);
+    c.arg("-version");
+    let out = super::run(c).with_context(|| format!("{java:?} from {source} can't run"))?;
+    let version = out
+        .lines()
+        .find(|l| l.contains("version"))
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+    let major = parse_major(&version)
+        .with_context(|| format!("unknown version of {java:?}: {version:?}"))?;
+    debug!("java {major} at {java:?} from {source}");
+    Ok(Runtime {
+        java,
+        home,
+        source,
+        version,
+        major,
+    })
+}
+
+/// the runtime is found once, errors are not cached
+pub(crate) fn runtime() -> Result<Runtime> {
+    if let Some(runtime) = RUNTIME.read().ok().and_then(|r| r.clone()) {
+        return Ok(runtime);
+    }
+    let runtime = probe()?;
+    if let Ok(mut cached) = RUNTIME.write() {
+        *cached = Some(runtime.clone());
+    }
+    Ok(runtime)
+}
+
+/// the runtime, if its version is at least `min` for `what`
+pub(crate) fn java_for(what: &str, min: u32) -> Result<Runtime> {
+    let runtime = runtime()?;
+    runtime.require(what, min)?;
+    Ok(runtime)
+}
+
+/// first line of `java -version`, e.g `openjdk version "11.0.2" 2019-01-15`
+pub(crate) fn java_version() -> Result<String> {
+    runtime().map(|r| r.version)
+}
+
+/// jadx and kotlinc pick the java of `JAVA_HOME`, make it the one rla uses
+pub(crate) fn with_java_home(c: &mut Command) {
+    if let Ok(Runtime {
+        home: Some(home), ..
+    }) = runtime()
+    {
+        c.env("JAVA_HOME", home);
+    }
+}
+
+impl Runtime {
+    pub fn require(&self, what: &str, min: u32) -> Result<()> {
+        if self.major >= min {
+            return Ok(());
+        }
+        Err(format_err!(
+            "{what} requires java {min}+, but {:?} from {} is java {}. \
+             set JAVA_HOME(or `java_home` of {}) to a jdk {min}+",
+            self.java,
+            self.source,
+            self.major,
+            crate::core::RLA_CONFIG
+        ))
+    }
+
+    /// javac next to java, a jre has no javac
+    pub fn javac(&self) -> Result<PathBuf> {
+        self.require("javac --release 8", MIN_JAVAC)?;
+        let javac = self.home.as_deref().map(|h| bin(h, "javac"));
+        match javac {
+            Some(javac) if javac.is_file() => Ok(javac),
+            _ if self.source == JavaSource::Path => super::which("javac").map_err(|_| {
+                format_err!(
+                    "javac not found, {:?} may be a jre, install a jdk",
+                    self.java
+                )
+            }),
+            _ => Err(format_err!(
+                "javac not found in {:?}(from {}), it may be a jre, use a jdk instead",
+                self.home.as_deref().unwrap_or_else(|| Path::new("")),
+                self.source
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_major;
+
+    #[test]
+    fn test_parse_major() {
+        assert_eq!(
+            parse_major(r#"java version "1.8.0_301""#),
+            Some(8),
+            "java 8 is 1.8"
+        );
+        assert_eq!(
+            parse_major(r#"openjdk version "17.0.2" 2022-01-18"#),
+            Some(17)
+        );
+        assert_eq!(parse_major(r#"openjdk version "21" 2023-09-19"#), Some(21));
+        assert_eq!(parse_major(r#"openjdk version "9-ea""#), Some(9));
+        assert_eq!(parse_major("no version"), None);
+    }
+}
@@ -1,14 +1,24 @@
+mod adb;
+mod diagnostic;
+mod java;
 mod shell;
+mod worker;
 
 use std::process::{Command, Stdio};
 
 use anyhow::{format_err, Context, Result};
 use tracing::debug;
 
+pub(crate) use adb::{Adb, Install};
+pub(crate) use diagnostic::{Diagnostic, Tool, ToolError};
+pub(crate) use java::{java_version, runtime as java_runtime, set_java_home, MIN_D8};
 pub(crate) use shell::{
-    baksmali, compile_java, debugsign, dx_class_to_dex, git_add, git_commit, git_init,
-    jadx_compile_smali, jadx_extract_src, run_jar, smali, unzip, zip_update_files,
+    baksmali, cfr, compile_java, compile_kotlin, cwd, debugsign, dex2jar, dx_class_to_dex,
+    dx_to_dex, git_add, git_commit, git_head, git_init, git_status, jadx_decompile,
+    jadx_extract_src, jadx_path, jar_command, krakatau, procyon, run_jar, smali, unzip, vineflower,
+    which, zip_update_files,
 };
+pub(crate) use worker::enable_jvm_worker;
 
 fn cmd_to_string(cmd: &Command) -> String {
     let prog = cmd.get_program().to_str().unwrap_or("???");
@@ -23,14 +33,26 @@ fn cmd_to_string(cmd: &Command) -> String {
     vec.join(" ")
 }
 
+/// a missing program is the common case, point to `rla doctor`
+fn spawn_error(cmd: &Command, cmd_str: &str, e: std::io::Error) -> anyhow::Error {
+    let not_found = e.kind() == std::io::ErrorKind::NotFound;
+    let e = anyhow::Error::new(e).context(format!("failed to run {cmd_str:?}"));
+    if not_found {
+        let prog = cmd.get_program().to_string_lossy();
+        e.context(format!(
+            "{prog} not found, `rla doctor` checks the tools rla uses"
+        ))
+    } else {
+        e
+    }
+}
+
 pub(crate) fn run(mut cmd: Command) -> Result<String> {
     let cmd_str = cmd_to_string(&cmd);
     debug!("{:?}", cmd_str);
 
     cmd.stderr(Stdio::piped()).stdout(Stdio::piped());
-    let output = cmd
-        .output()
-        .with_context(|| format!("faile to run {cmd_str:?}"))?;
+    let output = cmd.output().map_err(|e| spawn_error(&cmd, &cmd_str, e))?;
 
     let mut msg = format!("{}", String::from_utf8_lossy(&output.stdout));
     if !output.stderr.is_empty() {
@@ -50,11 +72,12 @@ pub(crate) fn run(mut cmd: Command) -> Result<String> {
 }
 
 pub(crate) fn run_interit(cmd: Command) -> Result<()> {
-    fn run(mut cmd: Command) -> Result<()> {
-        Ok(cmd.status()?.exit_ok()?)
+    fn run(mut cmd: Command, cmd_str: &str) -> Result<()> {
+        let status = cmd.status().map_err(|e| spawn_error(&cmd, cmd_str, e))?;
+        Ok(status.exit_ok()?)
     }
 
     let cmd_str = cmd_to_string(&cmd);
     debug!("{:?}", cmd_str);
-    run(cmd).with_context(|| format!("{:?} error", cmd_str))
+    run(cmd, &cmd_str).with_context(|| format!("{:?} error", cmd_str))
 }
@@ -1,33 +1,78 @@
-use std::{ffi::OsStr, fs, path::Path, process::Command};
+use std::{
+    ffi::OsStr,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
-use crate::{
-    deps::{Dep, APK_SIGNER, DEBUG_STORE},
-    dir::binarydir,
+use super::{
+    diagnostic::{diagnose, Tool},
+    java::{java_for, with_java_home, MIN_D8, MIN_JAVA},
 };
+use crate::deps::{Dep, APK_SIGNER, DEBUG_STORE};
 use anyhow::{Context, Result};
 
+fn is_jar(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .map(|_| &magic == b"PK\x03\x04")
+        .unwrap_or(false)
+}
+
+/// a jar is run by `java -jar`, other files(e.g a wrapper script of a tool) are run directly
+pub(crate) fn jar_command(jar: &Path) -> Result<Command> {
+    if is_jar(jar) {
+        let name = jar.file_name().unwrap_or(jar.as_os_str()).to_string_lossy();
+        let runtime = java_for(&name, MIN_JAVA)?;
+        let mut c = Command::new(runtime.java);
+        c.arg("-jar").arg(jar);
+        Ok(c)
+    } else {
+        Ok(Command::new(jar))
+    }
+}
+
+/// `java -jar {jar} {args}`, in a jvm worker if it's enabled
+fn run_jar_job<T: AsRef<OsStr>>(jar: &Path, args: &[T]) -> Result<String> {
+    if is_jar(jar) {
+        if let Some(output) = super::worker::run_jar(jar, args) {
+            return output;
+        }
+    }
+    let mut c = jar_command(jar)?;
+    c.args(args);
+    super::run(c)
+}
+
+/// relative paths in the output of tools are relative to the working dir of rla
+pub(crate) fn cwd() -> PathBuf {
+    std::env::current_dir().unwrap_or_default()
+}
+
 /// run a jar file without capture output
 pub(crate) fn run_jar<T: AsRef<OsStr>>(jar: &'static Dep, args: &[T]) -> Result<()> {
-    let jarfile = jar.release_binary(binarydir())?;
-    let mut cmd = Command::new("java");
-    cmd.arg("-jar").arg(jarfile).args(args);
+    let jarfile = jar.cached()?;
+    let mut cmd = jar_command(&jarfile)?;
+    cmd.args(args);
     crate::cmd::run_interit(cmd)
 }
 
 pub(crate) fn debugsign(file: &Path) -> Result<()> {
-    let debug_store = DEBUG_STORE.release_binary(binarydir())?;
-    let apk_signer = APK_SIGNER.release_binary(binarydir())?;
-
-    let mut c = Command::new("java");
-    c.arg("-jar")
-        .arg(apk_signer)
-        .arg("sign")
-        .arg("--ks")
-        .arg(debug_store)
-        .arg("--ks-pass")
-        .arg("pass:android")
-        .arg(file);
-    super::run(c).map(|_| {
+    let debug_store = DEBUG_STORE.cached()?;
+    let apk_signer = APK_SIGNER.cached()?;
+
+    let args = [
+        OsStr::new("sign"),
+        OsStr::new("--ks"),
+        debug_store.as_os_str(),
+        OsStr::new("--ks-pass"),
+        OsStr::new("pass:android"),
+        file.as_os_str(),
+    ];
+    let result = run_jar_job(&apk_signer, &args);
+    diagnose(result, Tool::Apksigner, file).map(|_| {
         // Latest apksigner use v4 algorithm, which will create a extra file(xxx.idsig),
         // and I have no idea what is used for, just delete it :(
         // https://source.android.com/security/apksigning/v4
@@ -57,40 +102,115 @@ pub(crate) fn git_add(workdir: &Path) -> Result<String> {
     super::run(git)
 }
 
+/// `git status --porcelain`, empty if the work tree is clean
+pub(crate) fn git_status(workdir: &Path) -> Result<String> {
+    let mut git = Command::new("git");
+    git.current_dir(workdir).args(["status", "--porcelain"]);
+    super::run(git)
+}
+
+pub(crate) fn git_head(workdir: &Path) -> Result<String> {
+    let mut git = Command::new("git");
+    git.current_dir(workdir).args(["rev-parse", "HEAD"]);
+    super::run(git).map(|s| s.trim().to_string())
+}
+
+/// `${key}` if it's set, or `name` in `PATH`
+fn program_path(key: &str, name: &str) -> Result<PathBuf> {
+    match std::env::var_os(key) {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => which(name).with_context(|| format!("{name} not found, set it by `{key}`")),
+    }
+}
+
+/// `$RLA_JADX` or jadx in `PATH`
+pub(crate) fn jadx_path() -> Result<PathBuf> {
+    program_path("RLA_JADX", "jadx")
+}
+
+/// jadx fails with an unclear error on an old java, so the java is checked first
+fn jadx_command() -> Result<Command> {
+    java_for("jadx", MIN_D8)?;
+    let mut jadx = Command::new(jadx_path()?);
+    with_java_home(&mut jadx);
+    Ok(jadx)
+}
+
 pub(crate) fn jadx_extract_src(apk: &Path, outdir: &Path) -> Result<String> {
-    let mut jadx = Command::new("jadx");
+    let mut jadx = jadx_command()?;
     jadx.arg("-e").arg(apk).arg("-d").arg(outdir);
     super::run(jadx)
 }
 
-pub(crate) fn jadx_compile_smali(smali: &Path, outdir: &Path) -> Result<String> {
-    let mut jadx = Command::new("jadx");
-    jadx.arg("-d").arg(outdir).arg(smali);
+pub(crate) fn jadx_decompile(input: &Path, outdir: &Path) -> Result<String> {
+    let mut jadx = jadx_command()?;
+    jadx.arg("-d").arg(outdir).arg(input);
     super::run(jadx)
 }
 
-pub(crate) fn baksmali(dex: &Path, outdir: &Path, baksmali_jar: &Path) -> Result<String> {
-    let mut c = Command::new("java");
-    c.arg("-jar")
-        .arg(baksmali_jar)
-        .arg("d")
-        .arg(dex)
-        .arg("-o")
-        .arg(outdir);
+/// `d2j-dex2jar` from https://github.com/pxb1988/dex2jar
+pub(crate) fn dex2jar(dex2jar: &Path, input: &Path, out_jar: &Path) -> Result<String> {
+    let mut c = Command::new(dex2jar);
+    c.arg("-f").arg("-o").arg(out_jar).arg(input);
     super::run(c)
 }
 
-pub(crate) fn smali(smali_dir: &Path, dex: &Path, smali_jar: &Path) -> Result<String> {
-    let mut c = Command::new("java");
-    c.arg("-jar")
-        .arg(smali_jar)
-        .arg("a")
-        .arg(smali_dir)
-        .arg("-o")
-        .arg(dex);
+pub(crate) fn cfr(cfr_jar: &Path, jar: &Path, outdir: &Path) -> Result<String> {
+    let mut c = jar_command(cfr_jar)?;
+    c.arg(jar).arg("--outputdir").arg(outdir);
     super::run(c)
 }
 
+pub(crate) fn procyon(procyon_jar: &Path, jar: &Path, outdir: &Path) -> Result<String> {
+    let mut c = jar_command(procyon_jar)?;
+    c.arg("-jar").arg(jar).arg("-o").arg(outdir);
+    super::run(c)
+}
+
+/// vineflower(fernflower) saves sources of a jar to a jar with same name at `outdir`
+pub(crate) fn vineflower(vineflower_jar: &Path, jar: &Path, outdir: &Path) -> Result<String> {
+    let mut c = jar_command(vineflower_jar)?;
+    c.arg(jar).arg(outdir);
+    super::run(c)
+}
+
+/// krakatau decompiler(v1) is a python script, run by `$RLA_PYTHON` or python in `PATH`
+pub(crate) fn krakatau(decompile_py: &Path, jar: &Path, outdir: &Path) -> Result<String> {
+    let mut c = Command::new(program_path("RLA_PYTHON", "python")?);
+    c.arg(decompile_py)
+        .arg("-skip")
+        .arg("-out")
+        .arg(outdir)
+        .arg(jar);
+    super::run(c)
+}
+
+/// `options` are extra options of `baksmali d`, e.g `--sequential-labels`
+pub(crate) fn baksmali(
+    dex: &Path,
+    outdir: &Path,
+    baksmali_jar: &Path,
+    options: &[String],
+) -> Result<String> {
+    let mut args = vec![OsStr::new("d")];
+    args.extend(options.iter().map(OsStr::new));
+    args.extend([dex.as_os_str(), OsStr::new("-o"), outdir.as_os_str()]);
+    run_jar_job(baksmali_jar, &args)
+}
+
+/// `options` are extra options of `smali a`, e.g `--verbose`
+pub(crate) fn smali(
+    smali_dir: &Path,
+    dex: &Path,
+    smali_jar: &Path,
+    options: &[String],
+) -> Result<String> {
+    let mut args = vec![OsStr::new("a")];
+    args.extend(options.iter().map(OsStr::new));
+    args.extend([smali_dir.as_os_str(), OsStr::new("-o"), dex.as_os_str()]);
+    diagnose(run_jar_job(smali_jar, &args), Tool::Smali, &cwd())
+}
+
 pub(crate) fn unzip(apk: &Path, dir: &Path) -> Result<String> {
     let mut c = Command::new("unzip");
     c.arg("-n").arg("-d").arg(dir).arg(apk);
@@ -99,14 +219,35 @@ pub(crate) fn unzip(apk: &Path, dir: &Path) -> Result<String> {
 
 /// compile java requires working dir to java root
 pub(crate) fn compile_java<P: AsRef<OsStr>>(java_files: &[P], work_dir: &Path) -> Result<String> {
-    let mut c = Command::new("javac");
+    let javac = super::java::runtime()?.javac()?;
+    let mut c = Command::new(javac);
     c.current_dir(work_dir)
         .arg("--release")
         .arg("8")
         .args(java_files);
+    diagnose(super::run(c), Tool::Javac, work_dir)
+}
+
+pub(crate) fn compile_kotlin<P: AsRef<OsStr>>(kotlin_files: &[P], outdir: &Path) -> Result<String> {
+    let mut c = Command::new("kotlinc");
+    with_java_home(&mut c);
+    c.arg("-jvm-target")
+        .arg("1.8")
+        .arg("-d")
+        .arg(outdir)
+        .args(kotlin_files);
     super::run(c)
 }
 
+/// find an executable in `PATH`
+pub(crate) fn which(name: &str) -> Result<PathBuf> {
+    let paths = std::env::var_os("PATH").context("PATH is not set")?;
+    std::env::split_paths(&paths)
+        .map(|p| p.join(name))
+        .find(|p| p.is_file())
+        .with_context(|| format!("{name:?} not found in PATH"))
+}
+
 /// dx compile requires working to java root
 pub(crate) fn dx_class_to_dex<P: AsRef<OsStr>>(
     class_files: &[P],
@@ -123,15 +264,28 @@ pub(crate) fn dx_class_to_dex<P: AsRef<OsStr>>(
         })
         .collect::<Result<Vec<_>>>()?;
 
-    let mut c = Command::new("java");
+    let mut c = jar_command(dx_jar)?;
     c.current_dir(work_dir)
-        .arg("-jar")
-        .arg(dx_jar)
         .arg("--dex")
         .arg("--output")
         .arg(out_dex)
         .args(class_files);
-    super::run(c)
+    diagnose(super::run(c), Tool::Dx, work_dir)
+}
+
+/// inputs can be class file directories or jar files
+pub(crate) fn dx_to_dex<P: AsRef<OsStr>>(
+    inputs: &[P],
+    dx_jar: &Path,
+    out_dex: &Path,
+) -> Result<String> {
+    let mut args = vec![
+        OsStr::new("--dex"),
+        OsStr::new("--output"),
+        out_dex.as_os_str(),
+    ];
+    args.extend(inputs.iter().map(AsRef::as_ref));
+    diagnose(run_jar_job(dx_jar, &args), Tool::Dx, &cwd())
 }
 
 pub(crate) fn zip_update_files<P: AsRef<OsStr>>(
@@ -140,6 +294,11 @@ pub(crate) fn zip_update_files<P: AsRef<OsStr>>(
     files: &[P],
 ) -> Result<String> {
     let mut c = Command::new("zip");
-    c.current_dir(workdir).arg(apk).args(files);
+    // native libraries and resources table may be required to be stored uncompressed
+    c.current_dir(workdir)
+        .arg("-n")
+        .arg(".so:.arsc")
+        .arg(apk)
+        .args(files);
     super::run(c)
 }
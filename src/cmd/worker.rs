@@ -0,0 +1,187 @@
+//! persistent jvms running jars of tools, which saves the jvm startup of each run.
+//! they are enabled by commands running many jobs(e.g pack, unpack, watch), `RLA_NO_JVM_WORKER` disables them.
+//! a job falls back to `java -jar` if no worker can run it
+
+use std::{
+    ffi::OsStr,
+    io::{BufRead, BufReader, Read, Write},
+    path::Path,
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::{format_err, Context, Result};
+use once_cell::sync::Lazy;
+use tracing::{debug, warn};
+
+use super::java::{java_for, MIN_JAVA};
+use crate::deps::JVM_WORKER;
+
+/// `System.exit` of tools is trapped by a security manager, which is removed in java 24
+const MAX_JAVA: u32 = 23;
+/// the security manager is disallowed by default since java 18
+const ALLOW_SECURITY_MANAGER: u32 = 18;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// a worker failed to start, jobs are run by `java -jar` since then
+static BROKEN: AtomicBool = AtomicBool::new(false);
+static IDLE: Lazy<Mutex<Vec<Worker>>> = Lazy::new(Default::default);
+
+/// run following jar jobs in workers
+pub(crate) fn enable_jvm_worker() {
+    if std::env::var_os("RLA_NO_JVM_WORKER").is_none() {
+        ENABLED.store(true, Ordering::Relaxed);
+    }
+}
+
+struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+struct Output {
+    status: i32,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+impl Worker {
+    fn spawn() -> Result<Self> {
+        let runtime = java_for("jvm worker", MIN_JAVA)?;
+        if runtime.major > MAX_JAVA {
+            return Err(format_err!(
+                "jvm worker requires java {MAX_JAVA} or older, {:?} is java {}",
+                runtime.java,
+                runtime.major
+            ));
+        }
+        let jar = JVM_WORKER.cached()?;
+        let mut c = Command::new(&runtime.java);
+        if runtime.major >= ALLOW_SECURITY_MANAGER {
+            c.arg("-Djava.security.manager=allow");
+        }
+        c.arg("-jar")
+            .arg(&jar)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        let mut child = c.spawn().context("jvm worker start error")?;
+        let stdin = child.stdin.take().context("no stdin of jvm worker")?;
+        let stdout = BufReader::new(child.stdout.take().context("no stdout of jvm worker")?);
+        let mut worker = Self {
+            child,
+            stdin,
+            stdout,
+        };
+        let ready = worker.read_string()?;
+        if ready != "ready" {
+            return Err(format_err!("jvm worker is not ready: {ready:?}"));
+        }
+        debug!("jvm worker {} started", worker.child.id());
+        Ok(worker)
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        let mut buf = Vec::new();
+        self.stdout.read_until(0, &mut buf)?;
+        if buf.pop() != Some(0) {
+            return Err(format_err!("jvm worker exited"));
+        }
+        Ok(String::from_utf8(buf)?)
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.read_string()?.parse()?;
+        let mut buf = vec![0; len];
+        self.stdout.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn call(&mut self, jar: &str, args: &[&str]) -> Result<Output> {
+        let mut request = Vec::new();
+        for s in [&(args.len() + 1).to_string(), jar]
+            .into_iter()
+            .chain(args.iter().copied())
+        {
+            request.extend_from_slice(s.as_bytes());
+            request.push(0);
+        }
+        self.stdin.write_all(&request)?;
+        self.stdin.flush()?;
+        Ok(Output {
+            status: self.read_string()?.parse()?,
+            stdout: self.read_bytes()?,
+            stderr: self.read_bytes()?,
+        })
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// an idle worker, or a new one if all are busy
+fn take() -> Option<Worker> {
+    if let Some(worker) = IDLE.lock().ok()?.pop() {
+        return Some(worker);
+    }
+    match Worker::spawn() {
+        Ok(worker) => Some(worker),
+        Err(e) => {
+            warn!("{e:#}, jars are run by `java -jar`");
+            BROKEN.store(true, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+/// run `java -jar {jar} {args}` in a worker, output is same as `cmd::run`.
+/// `None` if workers are disabled or broken, the job should be run by `java -jar`
+pub(crate) fn run_jar<S: AsRef<OsStr>>(jar: &Path, args: &[S]) -> Option<Result<String>> {
+    if !ENABLED.load(Ordering::Relaxed) || BROKEN.load(Ordering::Relaxed) {
+        return None;
+    }
+    // the protocol is utf-8
+    let jar_str = jar.to_str()?;
+    let args = args
+        .iter()
+        .map(|a| a.as_ref().to_str())
+        .collect::<Option<Vec<_>>>()?;
+    let cmd_str = format!("java -jar {jar_str} {}", args.join(" "));
+    debug!("{cmd_str:?} in jvm worker");
+
+    let mut worker = take()?;
+    let output = match worker.call(jar_str, &args) {
+        Ok(output) => output,
+        Err(e) => {
+            // the worker may be killed, don't reuse it
+            warn!("jvm worker error: {e:#}, {cmd_str:?} is run by `java -jar`");
+            return None;
+        }
+    };
+    if let Ok(mut idle) = IDLE.lock() {
+        idle.push(worker);
+    }
+
+    let mut msg = String::from_utf8_lossy(&output.stdout).to_string();
+    if !output.stderr.is_empty() {
+        if !msg.is_empty() {
+            msg.push('\n');
+        }
+        msg.push_str(&String::from_utf8_lossy(&output.stderr));
+    }
+    if output.status != 0 {
+        return Some(Err(format_err!(
+            "{cmd_str:?} exit code: exit status: {}, msg:\n{msg}",
+            output.status
+        )));
+    }
+    Some(Ok(msg))
+}
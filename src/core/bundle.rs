@@ -0,0 +1,175 @@
+//! split apks support, a bundle is a base apk with several split apks.
+//! bundle can be given as `.apks`(bundletool), `.xapk` or multiple `.apk` files
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{format_err, Context, Result};
+use tracing::debug;
+
+#[derive(Debug)]
+pub(crate) struct Bundle {
+    pub base: PathBuf,
+    /// (split name, apk path), split name is the file stem, e.g `split_config.arm64_v8a`
+    pub splits: Vec<(String, PathBuf)>,
+}
+
+fn has_ext(path: &Path, ext: &str) -> bool {
+    path.extension()
+        .map(|e| e.eq_ignore_ascii_case(ext))
+        .unwrap_or(false)
+}
+
+/// a bundle is a zip of apks
+pub(crate) fn is_bundle_file(path: &Path) -> bool {
+    has_ext(path, "apks")
+        || has_ext(path, "xapk")
+        || crate::zip::entry_names(path)
+            .map(|names| is_bundle_entries(&names))
+            .unwrap_or(false)
+}
+
+/// apks at the top level and no manifest, an apk may carry `.apk` in its assets
+pub(super) fn is_bundle_entries(names: &[String]) -> bool {
+    !names.iter().any(|n| n == "AndroidManifest.xml")
+        && names
+            .iter()
+            .any(|n| !n.contains('/') && n.ends_with(".apk"))
+}
+
+fn stem(path: &Path) -> Result<String> {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .map(str::to_string)
+        .with_context(|| format!("{path:?} file name invalid"))
+}
+
+/// xapk has a `manifest.json` which tells the base apk
+fn xapk_base(extracted: &Path) -> Option<PathBuf> {
+    let manifest = fs::read_to_string(extracted.join("manifest.json")).ok()?;
+    let manifest: serde_json::Value = serde_json::from_str(&manifest).ok()?;
+    manifest
+        .get("split_apks")?
+        .as_array()?
+        .iter()
+        .find(|s| s.get("id").and_then(|id| id.as_str()) == Some("base"))
+        .and_then(|s| s.get("file")?.as_str())
+        .map(|f| extracted.join(f))
+}
+
+fn is_split_name(name: &str) -> bool {
+    name.starts_with("split_") || name.starts_with("config.") || name.starts_with("base-")
+}
+
+impl Bundle {
+    /// find out base apk from apk files, splits are the others
+    fn from_apks(mut apks: Vec<PathBuf>, base: Option<PathBuf>) -> Result<Self> {
+        let base = match base {
+            Some(base) => base,
+            None => {
+                let names = apks.iter().map(|p| stem(p)).collect::<Result<Vec<_>>>()?;
+                let by_name = names.iter().position(|n| n == "base" || n == "base-master");
+                let not_split = names
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, n)| !is_split_name(n))
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>();
+                let idx = match (by_name, not_split.as_slice()) {
+                    (Some(i), _) => i,
+                    (None, [i]) => *i,
+                    _ => return Err(format_err!("can't find base apk in {apks:?}")),
+                };
+                apks[idx].clone()
+            }
+        };
+        apks.retain(|p| p != &base);
+        let splits = apks
+            .into_iter()
+            .map(|p| Ok((stem(&p)?, p)))
+            .collect::<Result<Vec<_>>>()?;
+        debug!("base apk: {base:?}, splits: {splits:?}");
+        Ok(Self { base, splits })
+    }
+
+    /// `tmpdir` is used to extract `.apks` and `.xapk`
+    pub fn from_inputs(inputs: &[PathBuf], tmpdir: &Path) -> Result<Self> {
+        match inputs {
+            [file] if is_bundle_file(file) => {
+                crate::zip::unzip(
+                    file,
+                    tmpdir,
+                    Some(|name: &Path| {
+                        // standalone apks of bundletool are not splits
+                        (has_ext(name, "apk") && !name.starts_with("standalones"))
+                            || name == Path::new("manifest.json")
+                    }),
+                )
+                .with_context(|| format!("unzip {file:?} error"))?;
+                let apks = walkdir::WalkDir::new(tmpdir)
+                    .into_iter()
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_type().is_file() && has_ext(e.path(), "apk"))
+                    .map(|e| e.path().to_path_buf())
+                    .collect::<Vec<_>>();
+                Self::from_apks(apks, xapk_base(tmpdir))
+            }
+            [file] => Ok(Self {
+                base: file.clone(),
+                splits: vec![],
+            }),
+            files => Self::from_apks(files.to_vec(), None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{is_bundle_entries, Bundle};
+
+    #[test]
+    fn test_find_base() {
+        let apks = [
+            "split_config.arm64_v8a.apk",
+            "base.apk",
+            "split_config.en.apk",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+        let bundle = Bundle::from_apks(apks, None).unwrap();
+        assert_eq!(bundle.base, PathBuf::from("base.apk"));
+        assert_eq!(bundle.splits.len(), 2);
+        assert_eq!(bundle.splits[0].0, "split_config.arm64_v8a");
+
+        let apks = ["com.abc.apk", "config.arm64_v8a.apk"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        let bundle = Bundle::from_apks(apks, None).unwrap();
+        assert_eq!(bundle.base, PathBuf::from("com.abc.apk"));
+    }
+
+    #[test]
+    fn test_bundle_entries() {
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert!(is_bundle_entries(&names(&[
+            "base.apk",
+            "split_config.en.apk"
+        ])));
+        assert!(!is_bundle_entries(&names(&[
+            "AndroidManifest.xml",
+            "classes.dex",
+            "assets/plugin.apk"
+        ])));
+        assert!(!is_bundle_entries(&names(&[
+            "AndroidManifest.xml",
+            "plugin.apk"
+        ])));
+        assert!(!is_bundle_entries(&names(&["assets/plugin.apk"])));
+    }
+}
@@ -0,0 +1,155 @@
+//! decompiler backends, all of them decompile a dex(or apk) into java sources.
+//! backends except jadx work on class files, so dex is converted to jar by dex2jar first.
+//!
+//! jadx and dex2jar are looked up in `PATH`, others are external tools of `deps`,
+//! configured by `RLA_{KEY}` or `tools` of project config, e.g `RLA_CFR=/opt/cfr.jar`.
+//! `RLA_JADX`, `RLA_DEX2JAR` and `RLA_PYTHON`(runs krakatau) can be used if they are not in `PATH`
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use super::input::{InputKind, AAR_CLASSES_JAR};
+use crate::{
+    deps::{CFR, DEX2JAR, KRAKATAU, PROCYON, VINEFLOWER},
+    dir::{tempdir, temppath},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decompiler {
+    Jadx,
+    Cfr,
+    Procyon,
+    /// vineflower is a fork of fernflower, both of them are supported
+    Vineflower,
+    Krakatau,
+}
+
+impl Default for Decompiler {
+    fn default() -> Self {
+        Decompiler::Jadx
+    }
+}
+
+impl FromStr for Decompiler {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "jadx" => Ok(Decompiler::Jadx),
+            "cfr" => Ok(Decompiler::Cfr),
+            "procyon" => Ok(Decompiler::Procyon),
+            "vineflower" | "fernflower" => Ok(Decompiler::Vineflower),
+            "krakatau" => Ok(Decompiler::Krakatau),
+            _ => Err(format!(
+                "unknown decompiler {s:?}, available: jadx, cfr, procyon, vineflower, krakatau"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Decompiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Decompiler::Jadx => "jadx",
+            Decompiler::Cfr => "cfr",
+            Decompiler::Procyon => "procyon",
+            Decompiler::Vineflower => "vineflower",
+            Decompiler::Krakatau => "krakatau",
+        };
+        f.write_str(name)
+    }
+}
+
+/// the configured dex2jar, or `d2j-dex2jar` in `PATH`
+pub(crate) fn dex2jar_path() -> Result<PathBuf> {
+    match DEX2JAR.local() {
+        Some(_) => DEX2JAR.path(),
+        None => crate::cmd::which("d2j-dex2jar"),
+    }
+}
+
+impl Decompiler {
+    /// decompile a dex or apk into `outdir`, returns the root dir of java sources
+    pub(crate) fn decompile(self, input: &Path, outdir: &Path) -> Result<PathBuf> {
+        debug!("decompile {input:?} by {self}");
+        if self == Decompiler::Jadx {
+            crate::cmd::jadx_decompile(input, outdir)?;
+            return Ok(outdir.join("sources"));
+        }
+
+        let jar = temppath("tmp.jar");
+        crate::cmd::dex2jar(&dex2jar_path()?, input, jar.as_ref())?;
+        self.decompile_jar(jar.as_ref(), outdir)?;
+        Ok(outdir.to_path_buf())
+    }
+
+    /// decompile the whole input file, jadx exports it as a gradle project
+    pub(crate) fn export(self, input: &Path, kind: InputKind, outdir: &Path) -> Result<()> {
+        match (self, kind) {
+            (Decompiler::Jadx, _) => crate::cmd::jadx_extract_src(input, outdir).map(|_| ()),
+            (_, InputKind::Jar) => self.decompile_jar(input, outdir),
+            (_, InputKind::Aar) => {
+                let tmp = tempdir("tmp.aar")?;
+                crate::zip::unzip(
+                    input,
+                    tmp.path(),
+                    Some(|name: &Path| name == Path::new(AAR_CLASSES_JAR)),
+                )?;
+                self.decompile_jar(&tmp.path().join(AAR_CLASSES_JAR), outdir)
+            }
+            _ => self.decompile(input, outdir).map(|_| ()),
+        }
+    }
+
+    fn decompile_jar(self, jar: &Path, outdir: &Path) -> Result<()> {
+        fs::create_dir_all(outdir).with_context(|| format!("{outdir:?} create error"))?;
+        match self {
+            Decompiler::Jadx => crate::cmd::jadx_decompile(jar, outdir).map(|_| ()),
+            Decompiler::Cfr => crate::cmd::cfr(&CFR.path()?, jar, outdir).map(|_| ()),
+            Decompiler::Procyon => crate::cmd::procyon(&PROCYON.path()?, jar, outdir).map(|_| ()),
+            Decompiler::Vineflower => {
+                crate::cmd::vineflower(&VINEFLOWER.path()?, jar, outdir)?;
+                extract_source_jars(outdir)
+            }
+            Decompiler::Krakatau => {
+                crate::cmd::krakatau(&KRAKATAU.path()?, jar, outdir).map(|_| ())
+            }
+        }
+    }
+}
+
+/// sources may be saved as jar, extract them in place
+fn extract_source_jars(outdir: &Path) -> Result<()> {
+    for entry in fs::read_dir(outdir).with_context(|| format!("read dir {outdir:?} error"))? {
+        let path = entry?.path();
+        if path.extension().map(|e| e == "jar").unwrap_or(false) {
+            crate::zip::unzip(&path, outdir, None::<fn(&Path) -> bool>)
+                .with_context(|| format!("unzip {path:?} error"))?;
+            fs::remove_file(&path).ok();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Decompiler;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("CFR".parse::<Decompiler>().unwrap(), Decompiler::Cfr);
+        assert_eq!(
+            "fernflower".parse::<Decompiler>().unwrap(),
+            Decompiler::Vineflower
+        );
+        assert!("jd-gui".parse::<Decompiler>().is_err());
+    }
+}
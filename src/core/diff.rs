@@ -0,0 +1,330 @@
+//! diff of two apk(aar, jar, dex), entry by entry.
+//! dex at the root of archive are compared class by class, method bodies are compared by hash
+
+use std::{collections::BTreeMap, fs, io::Read, path::Path};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{
+    dex::{self, is_dex_magic, Class},
+    progress::outln,
+};
+
+/// (size, sha256)
+type Entries = BTreeMap<String, (u64, String)>;
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    name: String,
+    size: u64,
+    sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ChangedEntry {
+    name: String,
+    old_size: u64,
+    new_size: u64,
+    old_sha256: String,
+    new_sha256: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct EntryDiff {
+    added: Vec<Entry>,
+    removed: Vec<Entry>,
+    changed: Vec<ChangedEntry>,
+}
+
+/// methods are signatures, e.g `onCreate(Landroid/os/Bundle;)V`
+#[derive(Debug, Default, Serialize, PartialEq, Eq)]
+struct ClassChange {
+    name: String,
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct ClassDiff {
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<ClassChange>,
+}
+
+#[derive(Debug, Serialize)]
+struct Diff {
+    entries: EntryDiff,
+    classes: ClassDiff,
+}
+
+fn is_root_dex(name: &str) -> bool {
+    !name.contains('/') && name.ends_with(".dex")
+}
+
+fn is_dex_file(path: &Path) -> Result<bool> {
+    let mut magic = [0u8; 8];
+    let n = fs::File::open(path)
+        .and_then(|mut f| f.read(&mut magic))
+        .with_context(|| format!("{path:?} read error"))?;
+    Ok(is_dex_magic(&magic[..n]))
+}
+
+fn load_entries(path: &Path) -> Result<Entries> {
+    if is_dex_file(path)? {
+        let size = fs::metadata(path)?.len();
+        let mut entries = Entries::new();
+        entries.insert(
+            "classes.dex".to_string(),
+            (size, crate::hash::sha256_file(path)?),
+        );
+        return Ok(entries);
+    }
+    crate::zip::entry_hashes(path)
+}
+
+/// classes of all dex at the root, classes may move between dex after repacking
+fn load_classes(path: &Path) -> Result<BTreeMap<String, Class>> {
+    if is_dex_file(path)? {
+        return dex::classes(&fs::read(path)?).with_context(|| format!("{path:?} parse error"));
+    }
+    let mut classes = BTreeMap::new();
+    for name in crate::zip::dex_names(path)? {
+        let data = crate::zip::read_entry(path, &name)?;
+        classes.extend(dex::classes(&data).with_context(|| format!("{name} parse error"))?);
+    }
+    Ok(classes)
+}
+
+fn diff_entries(a: &Entries, b: &Entries) -> EntryDiff {
+    let mut diff = EntryDiff::default();
+    for (name, (size, sha256)) in a {
+        match b.get(name) {
+            None => diff.removed.push(Entry {
+                name: name.clone(),
+                size: *size,
+                sha256: sha256.clone(),
+            }),
+            Some((new_size, new_sha256)) if new_sha256 != sha256 => {
+                diff.changed.push(ChangedEntry {
+                    name: name.clone(),
+                    old_size: *size,
+                    new_size: *new_size,
+                    old_sha256: sha256.clone(),
+                    new_sha256: new_sha256.clone(),
+                })
+            }
+            _ => {}
+        }
+    }
+    for (name, (size, sha256)) in b {
+        if !a.contains_key(name) {
+            diff.added.push(Entry {
+                name: name.clone(),
+                size: *size,
+                sha256: sha256.clone(),
+            });
+        }
+    }
+    diff
+}
+
+fn diff_class(a: &Class, b: &Class) -> Option<ClassChange> {
+    let methods = |c: &Class| {
+        c.methods
+            .iter()
+            .map(|m| (m.signature.clone(), m.hash.clone()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let (old, new) = (methods(a), methods(b));
+    let mut change = ClassChange {
+        name: a.name.clone(),
+        ..Default::default()
+    };
+    for (sig, hash) in &old {
+        match new.get(sig) {
+            None => change.removed.push(sig.clone()),
+            Some(h) if h != hash => change.changed.push(sig.clone()),
+            _ => {}
+        }
+    }
+    change.added = new
+        .keys()
+        .filter(|sig| !old.contains_key(*sig))
+        .cloned()
+        .collect();
+
+    if change.added.is_empty() && change.removed.is_empty() && change.changed.is_empty() {
+        None
+    } else {
+        Some(change)
+    }
+}
+
+fn diff_classes(a: &BTreeMap<String, Class>, b: &BTreeMap<String, Class>) -> ClassDiff {
+    let mut diff = ClassDiff::default();
+    for (name, class) in a {
+        match b.get(name) {
+            None => diff.removed.push(name.clone()),
+            Some(new) => diff.changed.extend(diff_class(class, new)),
+        }
+    }
+    diff.added = b.keys().filter(|n| !a.contains_key(*n)).cloned().collect();
+    diff
+}
+
+fn short(hash: &str) -> &str {
+    &hash[..hash.len().min(12)]
+}
+
+fn print_text(diff: &Diff) {
+    let e = &diff.entries;
+    outln!(
+        "entries: {} added, {} removed, {} changed",
+        e.added.len(),
+        e.removed.len(),
+        e.changed.len()
+    );
+    for entry in &e.added {
+        outln!(
+            "  + {} ({} bytes, {})",
+            entry.name,
+            entry.size,
+            short(&entry.sha256)
+        );
+    }
+    for entry in &e.removed {
+        outln!(
+            "  - {} ({} bytes, {})",
+            entry.name,
+            entry.size,
+            short(&entry.sha256)
+        );
+    }
+    for entry in &e.changed {
+        outln!(
+            "  ~ {} ({} -> {} bytes, {} -> {})",
+            entry.name,
+            entry.old_size,
+            entry.new_size,
+            short(&entry.old_sha256),
+            short(&entry.new_sha256)
+        );
+    }
+
+    let c = &diff.classes;
+    outln!(
+        "classes: {} added, {} removed, {} changed",
+        c.added.len(),
+        c.removed.len(),
+        c.changed.len()
+    );
+    for name in &c.added {
+        outln!("  + {name}");
+    }
+    for name in &c.removed {
+        outln!("  - {name}");
+    }
+    for class in &c.changed {
+        outln!("  ~ {}", class.name);
+        for m in &class.added {
+            outln!("      + {m}");
+        }
+        for m in &class.removed {
+            outln!("      - {m}");
+        }
+        for m in &class.changed {
+            outln!("      ~ {m}");
+        }
+    }
+}
+
+pub(crate) fn run(a: &Path, b: &Path, json: bool) -> Result<()> {
+    let entries = diff_entries(&load_entries(a)?, &load_entries(b)?);
+
+    let dex_changed = entries
+        .added
+        .iter()
+        .chain(&entries.removed)
+        .map(|e| &e.name)
+        .chain(entries.changed.iter().map(|e| &e.name))
+        .any(|n| is_root_dex(n));
+    let classes = if dex_changed {
+        diff_classes(&load_classes(a)?, &load_classes(b)?)
+    } else {
+        ClassDiff::default()
+    };
+
+    let diff = Diff { entries, classes };
+    if json {
+        crate::progress::print_json(&diff)?;
+    } else {
+        print_text(&diff);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::dex::{Class, Method};
+
+    fn class(name: &str, methods: &[(&str, &str)]) -> (String, Class) {
+        let methods = methods
+            .iter()
+            .map(|(s, h)| Method {
+                signature: s.to_string(),
+                hash: h.to_string(),
+            })
+            .collect();
+        (
+            name.to_string(),
+            Class {
+                name: name.to_string(),
+                methods,
+            },
+        )
+    }
+
+    #[test]
+    fn test_diff_classes() {
+        let a = BTreeMap::from([
+            class("LA;", &[("a()V", "1"), ("b()V", "2")]),
+            class("LB;", &[]),
+        ]);
+        let b = BTreeMap::from([
+            class("LA;", &[("a()V", "1"), ("b()V", "3"), ("c()V", "4")]),
+            class("LC;", &[]),
+        ]);
+        let diff = super::diff_classes(&a, &b);
+        assert_eq!(diff.added, ["LC;"]);
+        assert_eq!(diff.removed, ["LB;"]);
+        assert_eq!(
+            diff.changed,
+            [super::ClassChange {
+                name: "LA;".to_string(),
+                added: vec!["c()V".to_string()],
+                removed: vec![],
+                changed: vec!["b()V".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_diff_entries() {
+        let a = BTreeMap::from([
+            ("a".to_string(), (1, "x".to_string())),
+            ("b".to_string(), (1, "y".to_string())),
+        ]);
+        let b = BTreeMap::from([
+            ("b".to_string(), (2, "z".to_string())),
+            ("c".to_string(), (1, "x".to_string())),
+        ]);
+        let diff = super::diff_entries(&a, &b);
+        assert_eq!(diff.added[0].name, "c");
+        assert_eq!(diff.removed[0].name, "a");
+        assert_eq!(diff.changed[0].new_size, 2);
+    }
+}
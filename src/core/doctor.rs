@@ -0,0 +1,139 @@
+//! check the external programs rla runs, with hints to fix them
+
+use std::{path::PathBuf, process::Command};
+
+use anyhow::{format_err, Result};
+
+use crate::{
+    cmd::{jadx_path, java_runtime, which, Adb, MIN_D8},
+    progress::outln,
+};
+
+struct Check {
+    name: &'static str,
+    /// commands can't work without it
+    required: bool,
+    /// what is it used by
+    usage: &'static str,
+    /// version and path
+    result: Result<(String, PathBuf)>,
+}
+
+fn first_line(out: &str, pattern: &str) -> String {
+    out.lines()
+        .map(str::trim)
+        .find(|l| l.contains(pattern))
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// a program in `PATH`, whose version is in the line of `pattern` of `{name} {arg}`
+fn program(name: &str, arg: &str, pattern: &str) -> Result<(String, PathBuf)> {
+    version(which(name)?, arg, pattern)
+}
+
+fn version(path: PathBuf, arg: &str, pattern: &str) -> Result<(String, PathBuf)> {
+    let mut c = Command::new(&path);
+    c.arg(arg);
+    let out = crate::cmd::run(c)?;
+    Ok((first_line(&out, pattern), path))
+}
+
+fn java() -> Result<(String, PathBuf)> {
+    let runtime = java_runtime()?;
+    let mut version = runtime.version.clone();
+    if runtime.major < MIN_D8 {
+        version.push_str(&format!(", d8 and jadx require java {MIN_D8}+"));
+    }
+    Ok((version, runtime.java))
+}
+
+fn javac() -> Result<(String, PathBuf)> {
+    let javac = java_runtime()?.javac()?;
+    let mut c = Command::new(&javac);
+    c.arg("-version");
+    let out = crate::cmd::run(c)?;
+    Ok((first_line(&out, "javac"), javac))
+}
+
+fn adb() -> Result<(String, PathBuf)> {
+    let adb = Adb::new(None, None)?;
+    Ok((adb.version()?, adb.bin().to_path_buf()))
+}
+
+pub(crate) fn run() -> Result<()> {
+    let checks = [
+        Check {
+            name: "java",
+            required: true,
+            usage: "runs smali, baksmali, apksigner and dx",
+            result: java(),
+        },
+        Check {
+            name: "javac",
+            required: false,
+            usage: "`rla cj` compiles java",
+            result: javac(),
+        },
+        Check {
+            name: "jadx",
+            required: false,
+            usage: "decompiles to jadx-src",
+            result: jadx_path().and_then(|path| version(path, "--version", "")),
+        },
+        Check {
+            name: "git",
+            required: false,
+            usage: "tracks changes of projects",
+            result: program("git", "--version", "git"),
+        },
+        Check {
+            name: "adb",
+            required: false,
+            usage: "`rla run`, `rla pull` and `rla pack --install`",
+            result: adb(),
+        },
+        Check {
+            name: "zip",
+            required: true,
+            usage: "packs apks",
+            result: program("zip", "-v", "This is Zip"),
+        },
+    ];
+
+    let mut missing = 0;
+    outln!("{:<8} {:<8} {:<44} path", "name", "status", "version");
+    for check in &checks {
+        match &check.result {
+            Ok((version, path)) => {
+                outln!("{:<8} {:<8} {version:<44} {path:?}", check.name, "ok")
+            }
+            Err(_) => {
+                let status = if check.required {
+                    missing += 1;
+                    "missing"
+                } else {
+                    "-"
+                };
+                outln!("{:<8} {status:<8} {:<44} -", check.name, "-");
+            }
+        }
+    }
+    if let Ok(runtime) = java_runtime() {
+        outln!("\njava is from {}", runtime.source);
+    }
+    for check in &checks {
+        if let Err(e) = &check.result {
+            outln!(
+                "\n{}, {}:\n  {}",
+                check.name,
+                check.usage,
+                format!("{e:#}").trim()
+            );
+        }
+    }
+    if missing > 0 {
+        return Err(format_err!("{missing} required programs are missing"));
+    }
+    Ok(())
+}
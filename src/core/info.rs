@@ -0,0 +1,301 @@
+//! overview of an apk or a project: manifest, native abis, dex, signing certificates and
+//! frameworks it's built with
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Result};
+use serde::Serialize;
+
+use super::{bundle, input::InputKind, manifest, payload, RlaConfig, BAK_APK, SPLITS};
+use crate::{axml, cert::Certificate, progress::outln};
+
+const COMPONENTS: &[&str] = &[
+    "activity",
+    "activity-alias",
+    "service",
+    "receiver",
+    "provider",
+];
+
+/// (entry prefix or suffix, framework)
+const FRAMEWORKS: &[(&str, &str)] = &[
+    ("/libflutter.so", "Flutter"),
+    ("assets/flutter_assets/", "Flutter"),
+    ("/libreactnativejni.so", "React Native"),
+    ("assets/index.android.bundle", "React Native"),
+    ("/libunity.so", "Unity"),
+    ("assets/bin/Data/", "Unity"),
+    ("/libmonodroid.so", "Xamarin"),
+    ("/libxamarin-app.so", "Xamarin"),
+    ("assemblies/", "Xamarin"),
+    ("assets/www/cordova.js", "Cordova"),
+    ("kotlin/", "Kotlin"),
+];
+
+#[derive(Debug, Serialize)]
+struct Component {
+    kind: String,
+    name: String,
+    /// explicit `android:exported`, or implied by intent filters
+    exported: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct DexInfo {
+    name: String,
+    classes: u32,
+    methods: u32,
+    fields: u32,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Info {
+    package: Option<String>,
+    version_code: Option<i64>,
+    version_name: Option<String>,
+    min_sdk: Option<i64>,
+    target_sdk: Option<i64>,
+    permissions: Vec<String>,
+    components: Vec<Component>,
+    splits: Vec<String>,
+    abis: Vec<String>,
+    dex: Vec<DexInfo>,
+    certificates: Vec<Certificate>,
+    frameworks: Vec<&'static str>,
+    packers: Vec<&'static str>,
+}
+
+fn read_manifest(info: &mut Info, apk: &Path) -> Result<()> {
+    let root = manifest::read(apk)?;
+    let package = manifest::package(&root).unwrap_or_default();
+    info.version_code = root.attr("versionCode").and_then(|v| v.as_int());
+    info.version_name = root.attr("versionName").map(|v| v.as_string());
+    if let Some(sdk) = root.children("uses-sdk").next() {
+        info.min_sdk = sdk.attr("minSdkVersion").and_then(|v| v.as_int());
+        info.target_sdk = sdk.attr("targetSdkVersion").and_then(|v| v.as_int());
+    }
+    info.permissions = root
+        .children
+        .iter()
+        .filter(|e| e.name.starts_with("uses-permission"))
+        .filter_map(|e| e.attr("name"))
+        .map(|v| v.as_string())
+        .collect();
+
+    for app in root.children("application") {
+        for e in app
+            .children
+            .iter()
+            .filter(|e| COMPONENTS.contains(&e.name.as_str()))
+        {
+            let name = e.attr("name").map(|v| v.as_string()).unwrap_or_default();
+            let exported = match e.attr("exported") {
+                Some(axml::Value::Bool(b)) => *b,
+                _ => e.children("intent-filter").next().is_some(),
+            };
+            info.components.push(Component {
+                kind: e.name.clone(),
+                name: manifest::full_class_name(&package, &name),
+                exported,
+            });
+        }
+    }
+    info.package = manifest::package(&root);
+    Ok(())
+}
+
+fn header_u32(dex: &[u8], off: usize) -> u32 {
+    dex.get(off..off + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .unwrap_or_default()
+}
+
+fn read_dex(apk: &Path, name: &str) -> Result<DexInfo> {
+    let dex = crate::zip::read_entry(apk, name)?;
+    if !super::input::is_dex_magic(&dex) {
+        return Err(format_err!("{name} is not a dex"));
+    }
+    Ok(DexInfo {
+        name: name.to_string(),
+        fields: header_u32(&dex, 0x50),
+        methods: header_u32(&dex, 0x58),
+        classes: header_u32(&dex, 0x60),
+    })
+}
+
+fn abi_of(entry: &str) -> Option<&str> {
+    let rest = entry.strip_prefix("lib/")?;
+    let (abi, file) = rest.split_once('/')?;
+    if file.is_empty() {
+        None
+    } else {
+        Some(abi)
+    }
+}
+
+fn detect_frameworks(names: &[String]) -> Vec<&'static str> {
+    let mut found = vec![];
+    for (pattern, framework) in FRAMEWORKS {
+        let matched = names.iter().any(|n| {
+            if pattern.starts_with('/') {
+                n.ends_with(pattern)
+            } else {
+                n.starts_with(pattern)
+            }
+        });
+        if matched && !found.contains(framework) {
+            found.push(*framework);
+        }
+    }
+    found
+}
+
+/// base apk and splits of a project or input file
+fn collect_apks(path: &Path, tmpdir: &Path) -> Result<(PathBuf, Vec<(String, PathBuf)>)> {
+    if path.is_dir() {
+        let config = RlaConfig::load(path)?;
+        if config.input != InputKind::Apk {
+            return Err(format_err!(
+                "info is not supported for {} project",
+                config.input
+            ));
+        }
+        let splits = config
+            .splits
+            .iter()
+            .map(|name| (name.clone(), path.join(SPLITS).join(name).join(BAK_APK)))
+            .collect();
+        return Ok((path.join(BAK_APK), splits));
+    }
+
+    let kind = InputKind::detect(path)?;
+    if kind != InputKind::Apk {
+        return Err(format_err!("info is not supported for {kind} file"));
+    }
+    let bundle = bundle::Bundle::from_inputs(&[path.to_path_buf()], tmpdir)?;
+    Ok((bundle.base, bundle.splits))
+}
+
+fn collect(path: &Path) -> Result<Info> {
+    let tmpdir = crate::dir::tempdir("tmp.info")?;
+    let (base, splits) = collect_apks(path, tmpdir.path())?;
+
+    let mut info = Info::default();
+    read_manifest(&mut info, &base)?;
+    for name in crate::zip::dex_names(&base)? {
+        info.dex.push(read_dex(&base, &name)?);
+    }
+    info.certificates = crate::cert::certificates(&base)?;
+
+    // native libs and assets may be in splits
+    let mut names = crate::zip::entry_names(&base)?;
+    for (name, apk) in &splits {
+        names.extend(crate::zip::entry_names(apk)?);
+        info.splits.push(name.clone());
+    }
+    for abi in names.iter().filter_map(|n| abi_of(n)) {
+        if !info.abis.iter().any(|a| a == abi) {
+            info.abis.push(abi.to_string());
+        }
+    }
+    info.abis.sort();
+    info.frameworks = detect_frameworks(&names);
+    for packer in names.iter().filter_map(|n| payload::match_packer(n)) {
+        if !info.packers.contains(&packer) {
+            info.packers.push(packer);
+        }
+    }
+    std::fs::remove_dir_all(&tmpdir).ok();
+    Ok(info)
+}
+
+fn print_text(info: &Info) {
+    let none = || "-".to_string();
+    let join = |v: &[&str]| if v.is_empty() { none() } else { v.join(", ") };
+    outln!(
+        "package:      {}",
+        info.package.clone().unwrap_or_else(none)
+    );
+    outln!(
+        "version:      {} ({})",
+        info.version_name.clone().unwrap_or_else(none),
+        info.version_code
+            .map(|v| v.to_string())
+            .unwrap_or_else(none)
+    );
+    outln!(
+        "sdk:          min {}, target {}",
+        info.min_sdk.map(|v| v.to_string()).unwrap_or_else(none),
+        info.target_sdk.map(|v| v.to_string()).unwrap_or_else(none)
+    );
+    if !info.splits.is_empty() {
+        outln!("splits:       {}", info.splits.join(", "));
+    }
+    outln!(
+        "abis:         {}",
+        join(&info.abis.iter().map(String::as_str).collect::<Vec<_>>())
+    );
+    outln!("frameworks:   {}", join(&info.frameworks));
+    if !info.packers.is_empty() {
+        outln!("packers:      {}", join(&info.packers));
+    }
+
+    outln!("dex:          {}", info.dex.len());
+    for dex in &info.dex {
+        outln!(
+            "  {:<16} {} classes, {} methods, {} fields",
+            dex.name,
+            dex.classes,
+            dex.methods,
+            dex.fields
+        );
+    }
+    outln!("certificates: {}", info.certificates.len());
+    for cert in &info.certificates {
+        outln!("  [{}] {}", cert.scheme, cert.subject);
+        outln!("       issuer {}", cert.issuer);
+        outln!("       valid {} - {}", cert.not_before, cert.not_after);
+        outln!("       sha256 {}", cert.sha256);
+    }
+    outln!("permissions:  {}", info.permissions.len());
+    for p in &info.permissions {
+        outln!("  {p}");
+    }
+    outln!("components:   {}", info.components.len());
+    for c in &info.components {
+        let exported = if c.exported { " (exported)" } else { "" };
+        outln!("  {:<15} {}{exported}", c.kind, c.name);
+    }
+}
+
+pub(crate) fn run(path: &Path, json: bool) -> Result<()> {
+    let info = collect(path)?;
+    if json {
+        crate::progress::print_json(&info)?;
+    } else {
+        print_text(&info);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_detect() {
+        let names = [
+            "lib/arm64-v8a/libflutter.so",
+            "lib/arm64-v8a/libapp.so",
+            "assets/index.android.bundle",
+            "lib/x86/",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            super::detect_frameworks(&names),
+            ["Flutter", "React Native"]
+        );
+        assert_eq!(super::abi_of(&names[0]), Some("arm64-v8a"));
+        assert_eq!(super::abi_of(&names[3]), None);
+    }
+}
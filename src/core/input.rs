@@ -0,0 +1,119 @@
+//! input file of a project, which is detected by content rather than file extension.
+//! pack produces the same type of file as the input
+
+use std::{
+    fmt, fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{format_err, Context, Result};
+use serde::{Deserialize, Serialize};
+
+pub(crate) use crate::dex::is_dex_magic;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputKind {
+    Apk,
+    /// android library, classes are at `classes.jar`
+    Aar,
+    Jar,
+    Dex,
+}
+
+impl Default for InputKind {
+    fn default() -> Self {
+        InputKind::Apk
+    }
+}
+
+impl fmt::Display for InputKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.ext())
+    }
+}
+
+pub(crate) const AAR_CLASSES_JAR: &str = "classes.jar";
+
+impl InputKind {
+    pub fn ext(self) -> &'static str {
+        match self {
+            InputKind::Apk => "apk",
+            InputKind::Aar => "aar",
+            InputKind::Jar => "jar",
+            InputKind::Dex => "dex",
+        }
+    }
+
+    /// the input is copied to project as `bak.{ext}`
+    pub(crate) fn backup(self, root: &Path) -> PathBuf {
+        root.join(format!("bak.{}", self.ext()))
+    }
+
+    /// bundles(`.apks`, `.xapk`) are detected as apk
+    pub(crate) fn detect(path: &Path) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        let n = fs::File::open(path)
+            .and_then(|mut f| f.read(&mut magic))
+            .with_context(|| format!("{path:?} read error"))?;
+        if is_dex_magic(&magic[..n]) {
+            return Ok(InputKind::Dex);
+        }
+        if !magic.starts_with(b"PK\x03\x04") {
+            return Err(format_err!("{path:?} is neither a zip nor a dex"));
+        }
+
+        let names = crate::zip::entry_names(path)?;
+        Self::from_entry_names(&names).with_context(|| format!("unknown file type of {path:?}"))
+    }
+
+    fn from_entry_names(names: &[String]) -> Option<Self> {
+        let has = |name: &str| names.iter().any(|n| n == name);
+        let has_root_dex = names
+            .iter()
+            .any(|n| !n.contains('/') && n.ends_with(".dex"));
+        if has("AndroidManifest.xml") && has(AAR_CLASSES_JAR) {
+            Some(InputKind::Aar)
+        } else if (has("AndroidManifest.xml") && has_root_dex)
+            || super::bundle::is_bundle_entries(names)
+        {
+            Some(InputKind::Apk)
+        } else if names.iter().any(|n| n.ends_with(".class")) || has("META-INF/MANIFEST.MF") {
+            Some(InputKind::Jar)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InputKind;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_entry_names() {
+        let apk = names(&["AndroidManifest.xml", "classes.dex", "res/a.xml"]);
+        assert_eq!(InputKind::from_entry_names(&apk), Some(InputKind::Apk));
+        let aar = names(&["AndroidManifest.xml", "classes.jar", "R.txt"]);
+        assert_eq!(InputKind::from_entry_names(&aar), Some(InputKind::Aar));
+        let jar = names(&["META-INF/MANIFEST.MF", "com/abc/Foo.class"]);
+        assert_eq!(InputKind::from_entry_names(&jar), Some(InputKind::Jar));
+        let xapk = names(&["manifest.json", "base.apk", "config.arm64_v8a.apk"]);
+        assert_eq!(InputKind::from_entry_names(&xapk), Some(InputKind::Apk));
+        // an apk in the assets of a jar doesn't make it a bundle
+        let jar = names(&["META-INF/MANIFEST.MF", "assets/plugin.apk"]);
+        assert_eq!(InputKind::from_entry_names(&jar), Some(InputKind::Jar));
+        assert_eq!(InputKind::from_entry_names(&names(&["a.txt"])), None);
+    }
+
+    #[test]
+    fn test_dex_magic() {
+        assert!(super::is_dex_magic(b"dex\n035\0abc"));
+        assert!(!super::is_dex_magic(b"PK\x03\x04"));
+    }
+}
@@ -2,22 +2,41 @@ use anyhow::{format_err, Context, Result};
 use once_cell::sync::Lazy;
 use regex::Regex;
 use std::{
+    collections::{BTreeSet, VecDeque},
     fs,
+    io::{BufReader, Read},
     path::{Path, PathBuf},
 };
-use tracing::debug;
+use tracing::{debug, info, warn};
 
 use crate::{
     deps::{BAKSMALI, DX},
-    dir::{binarydir, temppath},
+    dir::{tempdir, temppath},
 };
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lang {
+    Java,
+    Kotlin,
+}
+
+impl Lang {
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "java" => Some(Lang::Java),
+            "kt" => Some(Lang::Kotlin),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct JavaFile {
-    /// full path of java file to compile
+    /// full path of java(or kotlin) file to compile
     path: PathBuf,
     /// pacakge path of java, e.g "com/abc/def"
     package: PathBuf,
+    lang: Lang,
 }
 
 impl JavaFile {
@@ -29,9 +48,15 @@ impl JavaFile {
             p.strip_suffix(suffix).map(PathBuf::from)
         }
 
+        let lang =
+            Lang::from_path(path).with_context(|| format!("{path:?} is not java or kotlin"))?;
         let content = fs::read_to_string(&path).with_context(|| format!("{path:?} read error"))?;
         // java can have no pacakge
-        let package = parse_pacakge_path(&content).unwrap_or_default();
+        let package = match lang {
+            Lang::Java => parse_pacakge_path(&content),
+            Lang::Kotlin => parse_kotlin_pacakge_path(&content),
+        }
+        .unwrap_or_default();
 
         let (parent, _name) = match (path.parent(), path.file_name()) {
             (Some(parent), Some(name)) => (parent, name),
@@ -45,6 +70,7 @@ impl JavaFile {
             Self {
                 path: path.to_owned(),
                 package,
+                lang,
             },
             work_dir,
         ))
@@ -70,6 +96,7 @@ impl JavaFile {
 struct JavaFileContext {
     files: Vec<JavaFile>,
     work_dir: PathBuf,
+    lang: Lang,
 }
 
 impl JavaFileContext {
@@ -98,15 +125,24 @@ impl JavaFileContext {
             })
             .collect::<Result<Vec<_>>>()?;
         let base = base.context("bug??")?;
+        let lang = base.0.lang;
+        if let Some(f) = files.iter().find(|f| f.lang != lang) {
+            return Err(format_err!(
+                "java and kotlin can't be compiled together. {:?} VS {:?}",
+                base.0.path,
+                f.path
+            ));
+        }
 
         debug!(
-            "found {} java files, java root is {:?}",
+            "found {} {lang:?} files, java root is {:?}",
             files.len(),
             base.1
         );
         Ok(Self {
             files,
             work_dir: base.1,
+            lang,
         })
     }
 
@@ -121,14 +157,21 @@ impl JavaFileContext {
         self.files.iter().map(|e| e.class_file()).collect()
     }
 
-    fn compile(self) -> Result<()> {
+    fn compile(self, bundle_kotlin_stdlib: bool) -> Result<()> {
+        match self.lang {
+            Lang::Java => self.compile_java(),
+            Lang::Kotlin => self.compile_kotlin(bundle_kotlin_stdlib),
+        }
+    }
+
+    fn compile_java(self) -> Result<()> {
         // compile .java to .class(javac)
         crate::cmd::compile_java(&self.java_files(), &self.work_dir)?;
 
         // compile .class to .dex (dx)
         // .class should generated by below command
         let class_files = self.class_files();
-        let dx_jar = DX.release_binary(binarydir())?;
+        let dx_jar = DX.cached()?;
         let out_dex = temppath("tmp.dex");
         crate::cmd::dx_class_to_dex(&class_files, &self.work_dir, &dx_jar, out_dex.as_ref())?;
         // class files is not not need, remove it
@@ -137,13 +180,14 @@ impl JavaFileContext {
         }
 
         // compile .dex to .smali (baksmali)
-        let out_smalis = temppath("tmp.smalis");
-        let baksmali_jar = BAKSMALI.release_binary(binarydir())?;
-        crate::cmd::baksmali(out_dex.as_ref(), out_smalis.as_ref(), &baksmali_jar)?;
+        let out_smalis = tempdir("tmp.smalis")?;
+        let baksmali_jar = BAKSMALI.cached()?;
+        let options = baksmali_options(&self.work_dir);
+        crate::cmd::baksmali(out_dex.as_ref(), out_smalis.path(), &baksmali_jar, &options)?;
 
         // copy smali to dest dir
         for file in self.files {
-            let smali = file.smali_relative_to(out_smalis.as_ref())?;
+            let smali = file.smali_relative_to(out_smalis.path())?;
             let dest = file.path.with_extension("smali");
             fs::rename(&smali, &dest)
                 .with_context(|| format!("copy from {smali:?} to {dest:?} error"))?;
@@ -151,6 +195,199 @@ impl JavaFileContext {
 
         Ok(())
     }
+
+    /// a kotlin file may generate several classes (e.g `FooKt`, `Foo$Companion`),
+    /// so all generated smali files are copied to java root
+    fn compile_kotlin(self, bundle_stdlib: bool) -> Result<()> {
+        // compile .kt to .class (kotlinc)
+        let classes_dir = tempdir("tmp.classes")?;
+        crate::cmd::compile_kotlin(&self.java_files(), classes_dir.path())?;
+
+        // compile .class to .dex (dx), used classes of kotlin stdlib are also compiled if need to bundle
+        let mut inputs = vec![classes_dir.path().to_path_buf()];
+        let stdlib_dir = tempdir("tmp.stdlib")?;
+        if bundle_stdlib {
+            let jar = kotlin_stdlib_jar()?;
+            let count = extract_used_classes(classes_dir.path(), &jar, stdlib_dir.path())?;
+            info!("{count} classes of {jar:?} are bundled");
+            inputs.push(stdlib_dir.path().to_path_buf());
+        } else {
+            check_app_kotlin_stdlib(&self.work_dir);
+        }
+        let dx_jar = DX.cached()?;
+        let out_dex = temppath("tmp.dex");
+        crate::cmd::dx_to_dex(&inputs, &dx_jar, out_dex.as_ref())?;
+        drop(classes_dir);
+        drop(stdlib_dir);
+
+        // compile .dex to .smali (baksmali)
+        let out_smalis = tempdir("tmp.smalis")?;
+        let out_smalis = out_smalis.path();
+        let baksmali_jar = BAKSMALI.cached()?;
+        let options = baksmali_options(&self.work_dir);
+        crate::cmd::baksmali(out_dex.as_ref(), out_smalis, &baksmali_jar, &options)?;
+
+        // copy smali to dest dir
+        for smali in walkdir::WalkDir::new(out_smalis)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_file())
+        {
+            let name = smali.path().strip_prefix(out_smalis)?;
+            let dest = self.work_dir.join(name);
+            if let Some(p) = dest.parent() {
+                fs::create_dir_all(p).with_context(|| format!("{p:?} create error"))?;
+            }
+            fs::rename(smali.path(), &dest)
+                .with_context(|| format!("copy from {:?} to {dest:?} error", smali.path()))?;
+        }
+        fs::remove_dir_all(out_smalis).ok();
+
+        Ok(())
+    }
+}
+
+/// kotlin stdlib is shipped with kotlinc, at `$KOTLIN_HOME/lib/kotlin-stdlib.jar`
+fn kotlin_stdlib_jar() -> Result<PathBuf> {
+    let home = match std::env::var_os("KOTLIN_HOME") {
+        Some(home) => PathBuf::from(home),
+        None => {
+            // kotlinc is usually a symbol link to `$KOTLIN_HOME/bin/kotlinc`
+            let kotlinc = crate::cmd::which("kotlinc")?;
+            let kotlinc = fs::canonicalize(&kotlinc).unwrap_or(kotlinc);
+            kotlinc
+                .parent()
+                .and_then(Path::parent)
+                .map(Path::to_path_buf)
+                .with_context(|| format!("can't find kotlin home from {kotlinc:?}"))?
+        }
+    };
+    let jar = home.join("lib").join("kotlin-stdlib.jar");
+    if !jar.exists() {
+        return Err(format_err!("{jar:?} not exists, set KOTLIN_HOME to fix it"));
+    }
+    Ok(jar)
+}
+
+/// class names in the constant pool of a class file, including those in descriptors,
+/// e.g `kotlin/Unit` of `(Lkotlin/Unit;)V`
+fn class_refs(class: &[u8]) -> Result<BTreeSet<String>> {
+    static DESCRIPTOR: Lazy<Regex> = Lazy::new(|| Regex::new(r"L([^;()\[<>:]+)[;<]").unwrap());
+
+    let u16_at = |off: usize| {
+        class
+            .get(off..off + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+            .with_context(|| format!("unexpected eof of class at {off}"))
+    };
+    let count = u16_at(8)?;
+    let mut refs = BTreeSet::new();
+    let mut off = 10;
+    let mut i = 1;
+    while i < count {
+        let tag = *class.get(off).context("unexpected eof of class")?;
+        off += 1;
+        match tag {
+            // utf8
+            1 => {
+                let len = u16_at(off)?;
+                let bytes = class
+                    .get(off + 2..off + 2 + len)
+                    .context("unexpected eof of class")?;
+                let s = String::from_utf8_lossy(bytes);
+                if !s.contains([';', '(', ' ', '.']) && s.contains('/') {
+                    refs.insert(s.to_string());
+                }
+                refs.extend(DESCRIPTOR.captures_iter(&s).map(|c| c[1].to_string()));
+                off += 2 + len;
+            }
+            // long and double take two entries
+            5 | 6 => {
+                off += 8;
+                i += 1;
+            }
+            7 | 8 | 16 | 19 | 20 => off += 2,
+            15 => off += 3,
+            3 | 4 | 9 | 10 | 11 | 12 | 17 | 18 => off += 4,
+            _ => {
+                return Err(format_err!(
+                    "unknown constant pool tag {tag} at {}",
+                    off - 1
+                ))
+            }
+        }
+        i += 1;
+    }
+    Ok(refs)
+}
+
+/// classes of `jar` used by classes at `classes_dir`, and the ones they use, are extracted to `outdir`.
+/// it's a small part of a library(e.g kotlin stdlib), which is too big to be disassembled into a project
+fn extract_used_classes(classes_dir: &Path, jar: &Path, outdir: &Path) -> Result<usize> {
+    let reader =
+        BufReader::new(fs::File::open(jar).with_context(|| format!("{jar:?} open error"))?);
+    let mut archive =
+        zip::ZipArchive::new(reader).with_context(|| format!("{jar:?} is not a zip"))?;
+
+    let mut queue = VecDeque::new();
+    for entry in walkdir::WalkDir::new(classes_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().map(|e| e == "class").unwrap_or(false))
+    {
+        let class =
+            fs::read(entry.path()).with_context(|| format!("{:?} read error", entry.path()))?;
+        queue
+            .extend(class_refs(&class).with_context(|| format!("{:?} parse error", entry.path()))?);
+    }
+
+    let mut extracted = BTreeSet::new();
+    while let Some(name) = queue.pop_front() {
+        let entry = format!("{name}.class");
+        if extracted.contains(&entry) {
+            continue;
+        }
+        let mut class = vec![];
+        match archive.by_name(&entry) {
+            Ok(mut file) => file.read_to_end(&mut class)?,
+            // not a class of the jar, e.g `java/lang/Object`
+            Err(_) => continue,
+        };
+        let dest = outdir.join(&entry);
+        if let Some(p) = dest.parent() {
+            fs::create_dir_all(p).with_context(|| format!("{p:?} create error"))?;
+        }
+        fs::write(&dest, &class).with_context(|| format!("{dest:?} write error"))?;
+        queue
+            .extend(class_refs(&class).with_context(|| format!("{entry} of {jar:?} parse error"))?);
+        extracted.insert(entry);
+    }
+    Ok(extracted.len())
+}
+
+/// smali is generated in the style of the project, if it's in one
+fn baksmali_options(work_dir: &Path) -> Vec<String> {
+    super::find_rla_root_from(work_dir)
+        .and_then(|root| super::RlaConfig::load(&root).ok())
+        .map(|config| config.tool_options.baksmali)
+        .unwrap_or_default()
+}
+
+/// without bundled stdlib, compiled kotlin code references the stdlib in the app,
+/// tell user if the app doesn't have one
+fn check_app_kotlin_stdlib(work_dir: &Path) {
+    let root = match super::find_rla_root_from(work_dir) {
+        Some(root) => root,
+        None => return,
+    };
+    let found = fs::read_dir(root.join(super::SMALIS))
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .any(|e| e.path().join("kotlin").join("Unit.smali").exists());
+    if !found {
+        warn!("kotlin stdlib is not found in {root:?}, use `--kotlin-stdlib` to bundle it");
+    }
 }
 
 fn parse_pacakge_path(content: &str) -> Option<PathBuf> {
@@ -167,35 +404,49 @@ fn parse_pacakge_path(content: &str) -> Option<PathBuf> {
     Some(path)
 }
 
-fn compile_java_file(file: PathBuf) -> Result<()> {
+fn parse_kotlin_pacakge_path(content: &str) -> Option<PathBuf> {
+    // semicolon is optional in kotlin
+    static RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"(?m)^\s*package\s+(?P<pathes>(\w+\.)*\w+)\s*;?\s*$").unwrap());
+
+    let mut path = PathBuf::new();
+    RE.captures(content)?
+        .name("pathes")?
+        .as_str()
+        .split('.')
+        .filter(|s| !s.trim().is_empty())
+        .for_each(|s| path.push(s.trim()));
+    Some(path)
+}
+
+fn compile_java_file(file: PathBuf, bundle_kotlin_stdlib: bool) -> Result<()> {
     let ctx = JavaFileContext::from_files(&[file])?;
-    ctx.compile()?;
+    ctx.compile(bundle_kotlin_stdlib)?;
     Ok(())
 }
 
-fn compile_dir(dir: PathBuf) -> Result<()> {
+fn compile_dir(dir: PathBuf, bundle_kotlin_stdlib: bool) -> Result<()> {
     let files = walkdir::WalkDir::new(&dir)
         .into_iter()
         .filter_map(|e| e.ok())
-        .filter(|e| {
-            e.path().is_file() && e.path().extension().map(|s| s == "java").unwrap_or(false)
-        })
+        .filter(|e| e.path().is_file() && Lang::from_path(e.path()).is_some())
         .map(|e| e.path().to_path_buf())
         .collect::<Vec<_>>();
     if files.is_empty() {
-        return Err(format_err!("no java file found in {dir:?}"));
+        return Err(format_err!("no java or kotlin file found in {dir:?}"));
     }
     let ctx = JavaFileContext::from_files(&files)?;
-    ctx.compile()?;
+    ctx.compile(bundle_kotlin_stdlib)?;
     Ok(())
 }
 
-pub fn java_to_smali(path: &str) -> Result<()> {
+pub fn java_to_smali(path: &str, bundle_kotlin_stdlib: bool) -> Result<()> {
+    crate::cmd::enable_jvm_worker();
     let path = fs::canonicalize(path).with_context(|| format!("{path:?} not exists"))?;
-    if path.is_file() && path.extension().map(|ext| ext == "java").unwrap_or(false) {
-        compile_java_file(path)
+    if path.is_file() && Lang::from_path(&path).is_some() {
+        compile_java_file(path, bundle_kotlin_stdlib)
     } else if path.is_dir() {
-        compile_dir(path)
+        compile_dir(path, bundle_kotlin_stdlib)
     } else {
         Err(format_err!("{path:?} invalid"))
     }
@@ -210,4 +461,92 @@ mod tests {
             super::parse_pacakge_path("package com.bytedance.secsdk;").unwrap()
         );
     }
+
+    #[test]
+    fn test_kotlin_regex() {
+        let content = "// comment\npackage com.bytedance.secsdk\n\nimport a.b.C\n";
+        assert_eq!(
+            super::parse_kotlin_pacakge_path(content).unwrap(),
+            std::path::Path::new("com/bytedance/secsdk")
+        );
+        assert!(super::parse_kotlin_pacakge_path("fun main() {}").is_none());
+    }
+
+    /// a class file with its constant pool only
+    fn class_file(entries: &[&[u8]], count: u16) -> Vec<u8> {
+        let mut class = vec![0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 52];
+        class.extend(count.to_be_bytes());
+        entries.iter().for_each(|e| class.extend(*e));
+        class
+    }
+
+    fn utf8(s: &str) -> Vec<u8> {
+        let mut v = vec![1];
+        v.extend((s.len() as u16).to_be_bytes());
+        v.extend(s.as_bytes());
+        v
+    }
+
+    #[test]
+    fn test_class_refs() {
+        let class = class_file(
+            &[
+                &utf8("com/abc/Foo"),
+                &[7, 0, 1],
+                &[5, 0, 0, 0, 0, 0, 0, 0, 1],
+                &utf8("(Lkotlin/jvm/functions/Function0;)Lkotlin/Unit;"),
+                &utf8("[Lkotlin/Pair;"),
+                &utf8("hello kotlin/Unit."),
+            ],
+            8,
+        );
+        let refs = super::class_refs(&class).unwrap();
+        assert_eq!(
+            refs.into_iter().collect::<Vec<_>>(),
+            [
+                "com/abc/Foo",
+                "kotlin/Pair",
+                "kotlin/Unit",
+                "kotlin/jvm/functions/Function0"
+            ]
+        );
+        assert!(super::class_refs(&class[..20]).is_err());
+    }
+
+    #[test]
+    fn test_extract_used_classes() {
+        use std::{fs, io::Write};
+
+        let dir = tempfile::tempdir().unwrap();
+        let classes = dir.path().join("classes");
+        fs::create_dir(&classes).unwrap();
+        fs::write(
+            classes.join("Foo.class"),
+            class_file(&[&utf8("()Lkotlin/A;")], 2),
+        )
+        .unwrap();
+
+        // `kotlin/A` uses `kotlin/B`, `kotlin/C` is not used
+        let jar = dir.path().join("stdlib.jar");
+        let mut zip = zip::ZipWriter::new(fs::File::create(&jar).unwrap());
+        for (name, refs) in [
+            ("kotlin/A", "kotlin/B"),
+            ("kotlin/B", "java/lang/Object"),
+            ("kotlin/C", "kotlin/A"),
+        ] {
+            zip.start_file(format!("{name}.class"), Default::default())
+                .unwrap();
+            zip.write_all(&class_file(&[&utf8(refs)], 2)).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let out = dir.path().join("out");
+        assert_eq!(
+            super::extract_used_classes(&classes, &jar, &out).unwrap(),
+            2
+        );
+        assert!(out.join("kotlin/A.class").exists());
+        assert!(out.join("kotlin/B.class").exists());
+        assert!(!out.join("kotlin/C.class").exists());
+    }
 }
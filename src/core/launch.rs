@@ -0,0 +1,123 @@
+//! install the last output to a device by adb, start its launcher activity and show its logs
+
+use std::{
+    ffi::OsStr,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{format_err, Context, Result};
+use tracing::{info, warn};
+
+use super::{manifest, pack::BUNDLE_BASE, RunConfig};
+use crate::{
+    axml::Element,
+    cmd::{Adb, Install},
+};
+
+/// how long to wait for the process after the activity is started
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// apks of an output, the base apk is the first one for split apks
+pub(super) fn output_apks(output: &Path) -> Result<Vec<PathBuf>> {
+    if output.is_file() {
+        return Ok(vec![output.to_path_buf()]);
+    }
+    let mut splits = output
+        .read_dir()
+        .with_context(|| format!("{output:?} read error"))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension() == Some(OsStr::new("apk")) && !p.ends_with(BUNDLE_BASE))
+        .collect::<Vec<_>>();
+    splits.sort();
+    let mut apks = vec![output.join(BUNDLE_BASE)];
+    apks.extend(splits);
+    Ok(apks)
+}
+
+fn confirm(msg: &str) -> Result<bool> {
+    // stdout has json events only in json mode
+    if crate::progress::is_json() {
+        eprint!("{msg} [y/N] ");
+    } else {
+        print!("{msg} [y/N] ");
+        std::io::stdout().flush()?;
+    }
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+pub(super) fn install(adb: &Adb, package: &str, apks: &[PathBuf], yes: bool) -> Result<()> {
+    let msg = match adb.install(apks)? {
+        Install::Success => return Ok(()),
+        Install::SignatureMismatch(msg) => msg,
+    };
+    warn!(
+        "{package} on the device is signed by another key:\n{}",
+        msg.trim()
+    );
+    if !yes && !confirm(&format!("uninstall {package} (its data will be lost)?"))? {
+        return Err(format_err!("install canceled"));
+    }
+    adb.uninstall(package)?;
+    match adb.install(apks)? {
+        Install::Success => Ok(()),
+        Install::SignatureMismatch(msg) => Err(format_err!("install failed: {msg}")),
+    }
+}
+
+/// install an output, returns its manifest and package
+fn install_output(adb: &Adb, output: &Path, yes: bool) -> Result<(Element, String)> {
+    let apks = output_apks(output)?;
+    let manifest = manifest::read(&apks[0])?;
+    let package =
+        manifest::package(&manifest).with_context(|| format!("no package in {:?}", apks[0]))?;
+
+    info!("install {output:?}");
+    install(adb, &package, &apks, yes)?;
+    Ok((manifest, package))
+}
+
+/// install an output without starting it, e.g for `pack --install`
+pub(crate) fn install_only(output: &Path, config: &RunConfig) -> Result<()> {
+    let adb = Adb::new(config.adb.clone(), config.serial.clone())?;
+    install_output(&adb, output, config.yes).map(|_| ())
+}
+
+pub(crate) fn run(output: &Path, config: &RunConfig) -> Result<()> {
+    let adb = Adb::new(config.adb.clone(), config.serial.clone())?;
+    let (manifest, package) = install_output(&adb, output, config.yes)?;
+
+    let activity = manifest::launcher_activity(&manifest)
+        .with_context(|| format!("no launcher activity in {package}"))?;
+    info!("start {package}/{activity}");
+    adb.start(&package, &activity)?;
+    if config.no_log {
+        return Ok(());
+    }
+    let pid = adb.wait_pid(&package, START_TIMEOUT)?;
+    info!("logcat of {package}({pid})");
+    adb.logcat(pid)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn test_output_apks() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["split_b.apk", "base.apk", "split_a.apk", "base.apk.idsig"] {
+            fs::write(dir.path().join(name), []).unwrap();
+        }
+        let apks = super::output_apks(dir.path()).unwrap();
+        let names = apks
+            .iter()
+            .map(|p| p.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["base.apk", "split_a.apk", "split_b.apk"]);
+    }
+}
@@ -0,0 +1,110 @@
+//! helpers for the binary `AndroidManifest.xml` of apk
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::axml::{self, Element};
+
+const MANIFEST: &str = "AndroidManifest.xml";
+const ACTION_MAIN: &str = "android.intent.action.MAIN";
+const CATEGORY_LAUNCHER: &str = "android.intent.category.LAUNCHER";
+
+/// root element(`<manifest>`) of the manifest in apk
+pub(crate) fn read(apk: &Path) -> Result<Element> {
+    let manifest = crate::zip::read_entry(apk, MANIFEST)?;
+    axml::parse(&manifest).with_context(|| format!("{MANIFEST} of {apk:?} parse error"))
+}
+
+pub(crate) fn package(manifest: &Element) -> Option<String> {
+    manifest
+        .attr("package")
+        .map(|v| v.as_string())
+        .filter(|p| !p.is_empty())
+}
+
+/// `.Foo` and `Foo` are relative to package
+pub(crate) fn full_class_name(package: &str, name: &str) -> String {
+    if name.starts_with('.') {
+        format!("{package}{name}")
+    } else if !name.contains('.') && !package.is_empty() {
+        format!("{package}.{name}")
+    } else {
+        name.to_string()
+    }
+}
+
+fn has_value(filter: &Element, tag: &str, value: &str) -> bool {
+    filter
+        .children(tag)
+        .any(|e| e.attr("name").map(|v| v.as_string()).as_deref() == Some(value))
+}
+
+/// the activity(or alias) started from launcher, with full class name
+pub(crate) fn launcher_activity(manifest: &Element) -> Option<String> {
+    let package = package(manifest).unwrap_or_default();
+    manifest
+        .children("application")
+        .flat_map(|app| app.children.iter())
+        .filter(|e| e.name == "activity" || e.name == "activity-alias")
+        .find(|e| {
+            e.children("intent-filter").any(|f| {
+                has_value(f, "action", ACTION_MAIN) && has_value(f, "category", CATEGORY_LAUNCHER)
+            })
+        })
+        .and_then(|e| e.attr("name"))
+        .map(|name| full_class_name(&package, &name.as_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axml::{Element, Value};
+
+    fn element(name: &str, attr: Option<&str>, children: Vec<Element>) -> Element {
+        Element {
+            name: name.to_string(),
+            attrs: attr
+                .map(|v| vec![("name".to_string(), Value::String(v.to_string()))])
+                .unwrap_or_default(),
+            children,
+        }
+    }
+
+    #[test]
+    fn test_launcher_activity() {
+        let filter = element(
+            "intent-filter",
+            None,
+            vec![
+                element("action", Some(super::ACTION_MAIN), vec![]),
+                element("category", Some(super::CATEGORY_LAUNCHER), vec![]),
+            ],
+        );
+        let mut manifest = element(
+            "manifest",
+            None,
+            vec![element(
+                "application",
+                None,
+                vec![
+                    element("activity", Some(".Other"), vec![]),
+                    element("activity", Some(".Main"), vec![filter]),
+                ],
+            )],
+        );
+        manifest
+            .attrs
+            .push(("package".to_string(), Value::String("com.abc".to_string())));
+        assert_eq!(
+            super::launcher_activity(&manifest).as_deref(),
+            Some("com.abc.Main")
+        );
+    }
+
+    #[test]
+    fn test_full_class_name() {
+        assert_eq!(super::full_class_name("com.abc", ".Main"), "com.abc.Main");
+        assert_eq!(super::full_class_name("com.abc", "Main"), "com.abc.Main");
+        assert_eq!(super::full_class_name("com.abc", "a.b.C"), "a.b.C");
+    }
+}
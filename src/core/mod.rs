@@ -1,37 +1,63 @@
-//! base.apk
+//! bak.apk (or bak.aar, bak.jar, bak.dex, same as the input)
 //! output/
 //! smalis/
 //! unzipped/
-//! jadx-src (if jadx is available)
+//! jadx-src (if decompiler is available)
+//! splits/{name}/ (for split apks, each has its own bak.apk, smalis/, unzipped/)
 //! .git
 //! .gitignore
 //! .rla.config.json
+//! .rla/ (local state, e.g snapshots)
 
 use std::{
+    collections::BTreeMap,
     fs,
     path::{Path, PathBuf},
+    time::Instant,
 };
 
 use anyhow::{format_err, Context, Result};
 use serde::{Deserialize, Serialize};
-use tracing::debug;
+use tracing::{debug, info, warn};
 
+pub use decompiler::Decompiler;
+pub use input::InputKind;
 pub use java_to_smali::java_to_smali;
+pub use payload::Payload;
 pub use smali_to_java::smali_to_java;
 
 use crate::runtime::rt;
 
+mod bundle;
+mod decompiler;
+mod diff;
+mod doctor;
+mod info;
+mod input;
 mod java_to_smali;
+mod launch;
+mod manifest;
+mod output;
 mod pack;
+mod payload;
+mod pull;
 mod smali_to_java;
+mod snapshot;
+mod status;
+mod sync_java;
+mod tools;
 mod unpack;
+mod watch;
 
-const RLA_CONFIG: &str = ".rla.config.json";
+pub(crate) const RLA_CONFIG: &str = ".rla.config.json";
 const BAK_APK: &str = "bak.apk";
 const MINI_FRIDA: &str = "minifrida";
 const UNPACKED: &str = ".unpacked";
 const SMALIS: &str = "smalis";
 const JADX_SRC: &str = "jadx-src";
+const RLA_DIR: &str = ".rla";
+const SPLITS: &str = "splits";
+const OUTPUT: &str = "output";
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct RlaConfig {
@@ -39,11 +65,110 @@ pub struct RlaConfig {
     pub git_enable: bool,
     pub jadx_enable: bool,
     pub force_override: bool,
+    /// decompiler to generate jadx-src and to compile smali to java
+    #[serde(default)]
+    pub decompiler: Decompiler,
+    /// names of split apks, which are unpacked at `splits/{name}`
+    #[serde(default)]
+    pub splits: Vec<String>,
+    /// type of the unpacked file, pack makes the same type of file
+    #[serde(default)]
+    pub input: InputKind,
+    /// disassemble dex payloads hidden in apk
+    #[serde(default)]
+    pub payload_enable: bool,
+    /// disassembled payloads, which are put back to their origin paths when packing
+    #[serde(default)]
+    pub payloads: Vec<Payload>,
+    /// commit the project on each pack, with a message naming the output
+    #[serde(default)]
+    pub git_commit_on_pack: bool,
+    /// extra options of baksmali(unpack) and smali(pack)
+    #[serde(default)]
+    pub tool_options: ToolOptions,
+    /// local tools instead of embedded ones, e.g `"smali": "/opt/smali-3.0.3.jar"`,
+    /// relative paths are relative to the project root. `RLA_{KEY}` env vars go first
+    #[serde(default)]
+    pub tools: BTreeMap<String, PathBuf>,
+    /// jdk used by tools instead of `JAVA_HOME` or java in `PATH`, relative to the project root
+    #[serde(default)]
+    pub java_home: Option<PathBuf>,
+    /// dex failed to disassemble, e.g `classes3.dex`, `splits/{name}/classes2.dex`.
+    /// they are kept as files at `smalis/{dex}`, and packed with their original bytes
+    #[serde(default)]
+    pub raw_dex: Vec<String>,
+    /// unpack the rest when a dex fails to disassemble, it's not saved
+    #[serde(skip)]
+    pub keep_going: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ToolOptions {
+    /// options of `baksmali d`, e.g `--sequential-labels`, `--use-locals`, `--debug-info false`
+    pub baksmali: Vec<String>,
+    /// options of `smali a`, e.g `--verbose`
+    pub smali: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PackConfig {
+    /// decompile changed smali to refresh jadx-src after packing
+    pub refresh_java: bool,
+    /// commit the project after packing, even if `git_commit_on_pack` is disabled
+    pub commit: bool,
+    /// rebuild on each change of smali and unpacked files
+    pub watch: bool,
+    /// install by adb after packing, or after each rebuild in watch mode
+    pub install: Option<RunConfig>,
+    /// options of smali instead of the ones in config
+    pub smali_options: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RunConfig {
+    /// adb binary, default is `$RLA_ADB` or the one in `PATH`
+    pub adb: Option<PathBuf>,
+    /// serial of the device
+    pub serial: Option<String>,
+    /// uninstall without asking when the installed app is signed by another key
+    pub yes: bool,
+    /// don't show logcat after starting
+    pub no_log: bool,
+}
+
+impl RlaConfig {
+    fn load(root: &Path) -> Result<Self> {
+        let config = fs::read_to_string(root.join(RLA_CONFIG)).context("rla config read error")?;
+        let config: RlaConfig = serde_json::from_str(&config).context("config parse error")?;
+        debug!("config is {config:?}");
+        config.register_tools(root);
+        Ok(config)
+    }
+
+    fn register_tools(&self, root: &Path) {
+        for key in self.tools.keys() {
+            if !crate::deps::is_known(key) {
+                warn!("unknown tool {key:?} in config");
+            }
+        }
+        let tools = self
+            .tools
+            .iter()
+            .map(|(key, path)| (key.clone(), root.join(path)))
+            .collect();
+        crate::deps::set_overrides(tools);
+        crate::cmd::set_java_home(self.java_home.as_ref().map(|home| root.join(home)));
+    }
 }
 
 fn find_rla_root() -> Option<PathBuf> {
     let cur = std::env::current_dir().ok()?;
-    let mut cur = Some(&*cur);
+    find_rla_root_from(&cur)
+}
+
+fn find_rla_root_from(path: &Path) -> Option<PathBuf> {
+    let mut cur = Some(path);
     while let Some(dir) = cur {
         if dir.join(RLA_CONFIG).exists() {
             return Some(dir.to_path_buf());
@@ -54,39 +179,303 @@ fn find_rla_root() -> Option<PathBuf> {
     None
 }
 
-pub fn pack_apk(dir: Option<String>) -> Result<()> {
-    let root = dir
-        .map(PathBuf::from)
+fn project_root(dir: Option<String>) -> Result<PathBuf> {
+    dir.map(PathBuf::from)
         .or_else(find_rla_root)
-        .context("can't find project root")?;
-    debug!("pack apk at {root:?}");
+        .context("can't find project root")
+}
 
-    rt().block_on(pack::run(root))?;
-    Ok(())
+pub fn pack_apk(dir: Option<String>, pack_config: PackConfig) -> Result<()> {
+    let root = project_root(dir)?;
+    debug!("pack apk at {root:?}, {pack_config:?}");
+
+    let mut config = RlaConfig::load(&root)?;
+    if let Some(options) = &pack_config.smali_options {
+        config.tool_options.smali = options.clone();
+    }
+    if pack_config.watch {
+        return watch::run(&root, &config, &pack_config);
+    }
+    let output = pack_project(&root, &config, &pack_config)?;
+    match &pack_config.install {
+        Some(run_config) => launch::install_only(&output, run_config),
+        None => Ok(()),
+    }
 }
 
-pub fn unpack_apk(apk: &str, config: RlaConfig) -> Result<()> {
-    debug!("unpack apk: {apk}, {config:?}");
+fn pack_project(root: &Path, config: &RlaConfig, pack_config: &PackConfig) -> Result<PathBuf> {
+    if pack_config.refresh_java {
+        sync_java::check(config)?;
+    }
+    crate::cmd::enable_jvm_worker();
+    if !config.raw_dex.is_empty() {
+        info!(
+            "{} are not disassembled, their original bytes are packed",
+            config.raw_dex.join(", ")
+        );
+    }
+    let start = Instant::now();
+    let output = rt().block_on(pack::run(root.to_path_buf(), config.clone()))?;
+    let duration = start.elapsed();
+    let snapshot = status::save_snapshot(root, config, status::PACK_SNAPSHOT)?;
+    let mut meta = output::OutputMeta::new(root, config, &output, &snapshot, duration)?;
+    if pack_config.commit || config.git_commit_on_pack {
+        meta.git_commit = output::commit_output(root, config, &output);
+    }
+    meta.save(&output)?;
+    if pack_config.refresh_java {
+        sync_java::run(root, config)?;
+    }
+    Ok(output)
+}
+
+/// pack if sources are changed since the last pack, then install and start the app
+pub fn run_apk(dir: Option<String>, run_config: RunConfig) -> Result<()> {
+    let root = project_root(dir)?;
+    debug!("run apk at {root:?}, {run_config:?}");
 
-    let apk = Path::new(apk).to_path_buf();
-    if !apk.extension().map(|e| e.eq("apk")).eq(&Some(true)) {
-        return Err(format_err!("not .apk file"));
+    let config = RlaConfig::load(&root)?;
+    if config.input != InputKind::Apk {
+        return Err(format_err!(
+            "only apk projects can be run, not {}",
+            config.input
+        ));
+    }
+    let output = match pack::last_output(&root) {
+        Some(output) if !status::needs_pack(&root, &config)? => output,
+        _ => pack_project(&root, &config, &PackConfig::default())?,
     };
+    launch::run(&output, &run_config)
+}
 
-    // prepare write directory
-    let outdir = apk.with_extension("");
-    if outdir.exists() {
-        if config.force_override {
-            debug!("remove {outdir:?}");
-            fs::remove_dir_all(&outdir).with_context(|| format!("remove {outdir:?} error"))?
-        } else {
-            return Err(format_err!(
-                "{outdir:?} already exists, delete it or use --force"
-            ));
+/// show changes of the project since unpack and since the last pack
+pub fn status(dir: Option<String>, json: bool) -> Result<()> {
+    let root = project_root(dir)?;
+    debug!("status of {root:?}");
+    status::run(&root, &RlaConfig::load(&root)?, json)
+}
+
+/// list outputs, or show details of output `N`
+pub fn history(dir: Option<String>, show: Option<usize>) -> Result<()> {
+    let root = project_root(dir)?;
+    match show {
+        Some(idx) => output::show(&root, idx),
+        None => output::history(&root),
+    }
+}
+
+/// remove old outputs, the latest `keep` ones are kept
+pub fn clean(dir: Option<String>, keep: usize) -> Result<()> {
+    let root = project_root(dir)?;
+    debug!("clean outputs of {root:?}, keep {keep}");
+    output::clean(&root, keep)
+}
+
+pub fn sync_java(dir: Option<String>) -> Result<()> {
+    let root = project_root(dir)?;
+    debug!("sync java at {root:?}");
+
+    let config = RlaConfig::load(&root)?;
+    sync_java::run(&root, &config)
+}
+
+/// list tools with their versions, local tools of the project are included
+pub fn tools_list(dir: Option<String>) -> Result<()> {
+    if let Some(root) = dir.map(PathBuf::from).or_else(find_rla_root) {
+        RlaConfig::load(&root)?;
+    }
+    tools::list()
+}
+
+/// check external programs used by rla, the java of the project config is used
+pub fn doctor(dir: Option<String>) -> Result<()> {
+    if let Some(root) = dir.map(PathBuf::from).or_else(find_rla_root) {
+        RlaConfig::load(&root)?;
+    }
+    doctor::run()
+}
+
+/// show info of an apk(or bundle) file, or of the project at `path`
+pub fn info(path: Option<String>, json: bool) -> Result<()> {
+    let path = match path {
+        Some(p) => PathBuf::from(p),
+        None => project_root(None)?,
+    };
+    debug!("info of {path:?}");
+    info::run(&path, json)
+}
+
+/// compare two apk(aar, jar, dex) files
+pub fn diff(a: &str, b: &str, json: bool) -> Result<()> {
+    debug!("diff {a} {b}");
+    diff::run(a.as_ref(), b.as_ref(), json)
+}
+
+/// `files` is an apk, aar, jar, dex, a bundle(`.apks`, `.xapk`), or a base apk with its split apks
+pub fn unpack_apk(files: &[String], config: RlaConfig) -> Result<()> {
+    debug!("unpack apk: {files:?}, {config:?}");
+
+    let files = files.iter().map(PathBuf::from).collect::<Vec<_>>();
+    let apk = files.first().context("no apk file")?;
+    // a file without extension(e.g dumped dex) can't be the dir name
+    let outdir = match apk.extension() {
+        Some(_) => apk.with_extension(""),
+        None => apk.with_extension("rla"),
+    };
+    unpack_files(&files, outdir, config)
+}
+
+/// a hidden dir next to `dir`, e.g `.app.{kind}-{random}` for `app`, it's removed when dropped
+fn hidden_dir(dir: &Path, kind: &str) -> Result<tempfile::TempDir> {
+    let parent = match dir.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let name = dir.file_name().context("invalid project dir")?;
+    tempfile::Builder::new()
+        .prefix(&format!(".{}.{kind}-", name.to_string_lossy()))
+        .tempdir_in(parent)
+        .with_context(|| format!("create dir at {parent:?} error"))
+}
+
+/// the project is unpacked at `.app.rla-{random}` for `app`
+fn staging_dir(dir: &Path) -> Result<tempfile::TempDir> {
+    hidden_dir(dir, "rla")
+}
+
+/// move the unpacked project to `dir`, an existing project is kept as a backup until it's done
+fn replace_dir(new: &Path, dir: &Path) -> Result<()> {
+    if !dir.exists() {
+        return fs::rename(new, dir).with_context(|| format!("move {new:?} to {dir:?} error"));
+    }
+    let name = dir.file_name().context("invalid project dir")?;
+    // a unique dir, so backups left by other unpacks are never touched. it's kept on errors
+    let backup_dir = hidden_dir(dir, "rla-backup")?;
+    let backup = backup_dir.path().join(name);
+    fs::rename(dir, &backup).with_context(|| format!("move {dir:?} to {backup:?} error"))?;
+    let backup_dir = backup_dir.into_path();
+    if let Err(e) = fs::rename(new, dir) {
+        let e = anyhow::Error::new(e).context(format!("move {new:?} to {dir:?} error"));
+        return match fs::rename(&backup, dir) {
+            Ok(_) => {
+                fs::remove_dir(&backup_dir).ok();
+                Err(e)
+            }
+            Err(restore) => Err(e.context(format!(
+                "restore {dir:?} error: {restore}, the previous project is at {backup:?}"
+            ))),
+        };
+    }
+    debug!("remove the previous project {backup:?}");
+    if let Err(e) = fs::remove_dir_all(&backup_dir) {
+        warn!("remove the previous project {backup:?} error: {e}");
+    }
+    Ok(())
+}
+
+/// pull apks of an installed package by adb, and unpack them at `{package}-{version}`
+pub fn pull_apk(package: &str, run_config: RunConfig, config: RlaConfig) -> Result<()> {
+    debug!("pull {package}: {run_config:?}, {config:?}");
+
+    let adb = crate::cmd::Adb::new(run_config.adb, run_config.serial)?;
+    let pull_dir = crate::dir::tempdir("tmp.pull")?;
+    let apks = pull::pull_apks(&adb, package, pull_dir.path())?;
+    let outdir = PathBuf::from(pull::project_name(&apks[0])?);
+    unpack_files(&apks, outdir, config)?;
+    Ok(())
+}
+
+fn unpack_files(files: &[PathBuf], outdir: PathBuf, mut config: RlaConfig) -> Result<()> {
+    crate::cmd::enable_jvm_worker();
+    let apk = files.first().context("no apk file")?;
+    config.input = InputKind::detect(apk)?;
+    for file in &files[1..] {
+        if InputKind::detect(file)? != InputKind::Apk || config.input != InputKind::Apk {
+            return Err(format_err!("only apk files can be unpacked together"));
         }
     }
-    fs::create_dir(&outdir).with_context(|| format!("{outdir:?} create error"))?;
 
-    rt().block_on(unpack::run(outdir, apk, config))?;
+    if outdir.exists() && !config.force_override {
+        return Err(format_err!(
+            "{outdir:?} already exists, delete it or use --force"
+        ));
+    }
+    // the project is built next to `outdir`, a failed unpack leaves `outdir` as it was
+    let staging = staging_dir(&outdir)?;
+    debug!("unpack at {:?}", staging.path());
+
+    let bundle_dir = crate::dir::tempdir("tmp.bundle")?;
+    let bundle = bundle::Bundle::from_inputs(files, bundle_dir.path())?;
+    config.splits = bundle.splits.iter().map(|(name, _)| name.clone()).collect();
+    let existed = outdir.exists();
+    rt().block_on(unpack::run(staging.path().to_path_buf(), bundle, config))
+        .and_then(|_| replace_dir(staging.path(), &outdir))
+        .with_context(|| {
+            // `outdir` is gone only if it's not restored, the error tells where it is
+            if existed && outdir.exists() {
+                format!("unpack failed, {outdir:?} is not changed")
+            } else {
+                "unpack failed".to_string()
+            }
+        })?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{replace_dir, staging_dir};
+
+    #[test]
+    fn test_staging_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("app");
+        let staging = staging_dir(&dir).unwrap();
+        let path = staging.path().to_path_buf();
+        assert_eq!(path.parent(), Some(root.path()));
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with(".app.rla-"));
+        drop(staging);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_replace_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("app");
+        let new = root.path().join("new");
+        fs::create_dir(&new).unwrap();
+        fs::write(new.join("a"), "1").unwrap();
+        replace_dir(&new, &dir).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "1");
+
+        // the previous project is replaced, and its backup is removed
+        fs::create_dir(&new).unwrap();
+        fs::write(new.join("a"), "2").unwrap();
+        replace_dir(&new, &dir).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "2");
+        assert!(!new.exists());
+        assert_eq!(fs::read_dir(root.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_replace_dir_failed() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("app");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("a"), "1").unwrap();
+        // a backup left by another unpack is kept
+        let stale = root.path().join(".app.rla-backup-old");
+        fs::create_dir(&stale).unwrap();
+
+        let missing = root.path().join("missing");
+        assert!(replace_dir(&missing, &dir).is_err());
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "1");
+        assert!(stale.exists());
+        assert_eq!(fs::read_dir(root.path()).unwrap().count(), 2);
+    }
+}
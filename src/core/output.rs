@@ -0,0 +1,332 @@
+//! metadata of pack outputs, saved next to the output as `{name}.meta.json`,
+//! e.g `output/3.apk.meta.json`, `output/4.meta.json` for split apks at `output/4/`
+
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{format_err, Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use super::{
+    input::InputKind,
+    pack::{outputs, BUNDLE_BASE},
+    snapshot::Snapshot,
+    status::group_of,
+    RlaConfig,
+};
+use crate::{
+    deps::{APK_SIGNER, SMALI},
+    progress::outln,
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct Signer {
+    pub scheme: String,
+    pub subject: String,
+    /// sha256 of the certificate
+    pub sha256: String,
+}
+
+/// fields are optional for outputs packed by older versions
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct OutputMeta {
+    /// rfc3339 time when the output is packed
+    pub timestamp: String,
+    pub duration_ms: u64,
+    pub input: InputKind,
+    /// sha256 of the input file(`bak.apk`)
+    pub input_sha256: String,
+    /// sha256 of the output file, `None` for split apks
+    pub sha256: Option<String>,
+    /// source dir(e.g `smalis/classes.dex`, `.unpacked`) => hash of all files in it
+    pub sources: BTreeMap<String, String>,
+    pub signer: Option<Signer>,
+    /// tool => version
+    pub tools: BTreeMap<String, String>,
+    /// commit of the project which the output is built from
+    pub git_commit: Option<String>,
+}
+
+fn meta_path(output: &Path) -> PathBuf {
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(".meta.json");
+    output.with_file_name(name)
+}
+
+/// a hash for each source dir, from file hashes of the snapshot
+fn source_hashes(snapshot: &Snapshot) -> BTreeMap<String, String> {
+    let mut groups: BTreeMap<String, String> = BTreeMap::new();
+    for (name, hash) in &snapshot.files {
+        let (dir, item) = group_of(name);
+        let content = groups.entry(dir).or_default();
+        content.push_str(&format!("{item}\0{hash}\n"));
+    }
+    groups
+        .into_iter()
+        .map(|(dir, content)| (dir, crate::hash::sha256(content.as_bytes())))
+        .collect()
+}
+
+fn tools(input: InputKind) -> BTreeMap<String, String> {
+    let mut tools = BTreeMap::new();
+    let mut add = |name: &str, version: Option<String>| {
+        tools.insert(
+            name.to_string(),
+            version.unwrap_or_else(|| "unknown".to_string()),
+        );
+    };
+    match input {
+        InputKind::Apk => {
+            add(SMALI.key, SMALI.tool_version());
+            add(APK_SIGNER.key, APK_SIGNER.tool_version());
+        }
+        InputKind::Aar | InputKind::Dex => add(SMALI.key, SMALI.tool_version()),
+        InputKind::Jar => {
+            add(SMALI.key, SMALI.tool_version());
+            let dex2jar = super::decompiler::dex2jar_path().ok();
+            add("dex2jar", dex2jar.map(|p| p.to_string_lossy().to_string()));
+        }
+    }
+    match crate::cmd::java_version() {
+        Ok(version) => add("java", Some(version)),
+        Err(e) => warn!("java version unknown: {e}"),
+    }
+    tools
+}
+
+fn signer(apk: &Path) -> Option<Signer> {
+    match crate::cert::certificates(apk) {
+        Ok(certs) => certs.into_iter().next().map(|c| Signer {
+            scheme: c.scheme.to_string(),
+            subject: c.subject,
+            sha256: c.sha256,
+        }),
+        Err(e) => {
+            warn!("read certificate of {apk:?} error: {e}");
+            None
+        }
+    }
+}
+
+impl OutputMeta {
+    pub fn new(
+        root: &Path,
+        config: &RlaConfig,
+        output: &Path,
+        snapshot: &Snapshot,
+        duration: Duration,
+    ) -> Result<Self> {
+        let sha256 = if output.is_file() {
+            Some(crate::hash::sha256_file(output)?)
+        } else {
+            None
+        };
+        let signer = match config.input {
+            InputKind::Apk if output.is_dir() => signer(&output.join(BUNDLE_BASE)),
+            InputKind::Apk => signer(output),
+            _ => None,
+        };
+        Ok(Self {
+            timestamp: Local::now().to_rfc3339(),
+            duration_ms: duration.as_millis() as u64,
+            input: config.input,
+            input_sha256: crate::hash::sha256_file(&config.input.backup(root))?,
+            sha256,
+            sources: source_hashes(snapshot),
+            signer,
+            tools: tools(config.input),
+            git_commit: None,
+        })
+    }
+
+    pub fn load(output: &Path) -> Result<Option<Self>> {
+        let path = meta_path(output);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path).with_context(|| format!("{path:?} read error"))?;
+        let meta =
+            serde_json::from_str(&content).with_context(|| format!("{path:?} parse error"))?;
+        Ok(Some(meta))
+    }
+
+    pub fn save(&self, output: &Path) -> Result<()> {
+        let path = meta_path(output);
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("{path:?} write error"))
+    }
+}
+
+/// commit the project with a message naming the output, returns the commit hash.
+/// HEAD is used if there is nothing to commit
+pub(crate) fn commit_output(root: &Path, config: &RlaConfig, output: &Path) -> Option<String> {
+    if !config.git_enable || !root.join(".git").exists() {
+        warn!("git is not enabled for this project, skip commit");
+        return None;
+    }
+    let name = output
+        .strip_prefix(root)
+        .unwrap_or(output)
+        .to_string_lossy();
+    let commit = || -> Result<String> {
+        crate::cmd::git_add(root)?;
+        if crate::cmd::git_status(root)?.trim().is_empty() {
+            info!("nothing changed since the last commit");
+        } else {
+            crate::cmd::git_commit(root, &format!("Pack {name}"))?;
+        }
+        crate::cmd::git_head(root)
+    };
+    let progress = crate::progress::start("git-commit", None);
+    let result = commit();
+    progress.end(&result);
+    match result {
+        Ok(hash) => {
+            info!("{name} is built from commit {hash}");
+            Some(hash)
+        }
+        Err(e) => {
+            error!("{e:?}");
+            None
+        }
+    }
+}
+
+fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+fn short_time(timestamp: &str) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|_| "-".to_string())
+}
+
+/// list outputs with their metadata
+pub(crate) fn history(root: &Path) -> Result<()> {
+    let mut outputs = outputs(root);
+    outputs.sort();
+    if outputs.is_empty() {
+        outln!("no outputs");
+        return Ok(());
+    }
+    for (idx, path) in outputs {
+        let meta = OutputMeta::load(&path)?.unwrap_or_default();
+        let commit = meta.git_commit.as_deref().unwrap_or("-");
+        let signer = meta
+            .signer
+            .as_ref()
+            .map(|s| s.subject.as_str())
+            .unwrap_or("-");
+        outln!(
+            "{idx:>4}  {:<19}  {:>7}  {:<8}  {:<16}  {signer}",
+            short_time(&meta.timestamp),
+            format!("{:.1}s", meta.duration_ms as f64 / 1000.0),
+            &commit[..commit.len().min(8)],
+            relative(root, &path),
+        );
+    }
+    Ok(())
+}
+
+/// details of output `N`
+pub(crate) fn show(root: &Path, idx: usize) -> Result<()> {
+    let path = outputs(root)
+        .into_iter()
+        .find(|(i, _)| *i == idx)
+        .map(|(_, p)| p)
+        .with_context(|| format!("output {idx} not found"))?;
+    let meta = OutputMeta::load(&path)?
+        .ok_or_else(|| format_err!("no metadata for {}", relative(root, &path)))?;
+
+    outln!("output:       {}", relative(root, &path));
+    outln!("time:         {}", short_time(&meta.timestamp));
+    outln!("duration:     {:.2}s", meta.duration_ms as f64 / 1000.0);
+    outln!("input:        {} ({})", meta.input, meta.input_sha256);
+    if let Some(sha256) = &meta.sha256 {
+        outln!("sha256:       {sha256}");
+    }
+    outln!(
+        "git commit:   {}",
+        meta.git_commit.as_deref().unwrap_or("-")
+    );
+    match &meta.signer {
+        Some(s) => outln!("signer:       [{}] {} ({})", s.scheme, s.subject, s.sha256),
+        None => outln!("signer:       -"),
+    }
+    outln!("tools:");
+    for (tool, version) in &meta.tools {
+        outln!("  {tool:<16} {version}");
+    }
+    outln!("sources:");
+    for (dir, hash) in &meta.sources {
+        outln!("  {dir:<32} {hash}");
+    }
+    Ok(())
+}
+
+/// remove outputs except the latest `keep` ones
+pub(crate) fn clean(root: &Path, keep: usize) -> Result<()> {
+    let mut outputs = outputs(root);
+    outputs.sort_by_key(|(idx, _)| Reverse(*idx));
+    let removed = outputs.len().saturating_sub(keep);
+    for (_, path) in outputs.into_iter().skip(keep) {
+        if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        }
+        .with_context(|| format!("remove {path:?} error"))?;
+        fs::remove_file(meta_path(&path)).ok();
+        info!("removed {}", relative(root, &path));
+    }
+    info!("{removed} outputs removed, {keep} kept");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    #[test]
+    fn test_meta_path() {
+        assert_eq!(
+            super::meta_path(Path::new("output/3.apk")),
+            Path::new("output/3.apk.meta.json")
+        );
+        assert_eq!(
+            super::meta_path(Path::new("output/4")),
+            Path::new("output/4.meta.json")
+        );
+    }
+
+    #[test]
+    fn test_source_hashes() {
+        let snapshot = super::Snapshot {
+            files: [
+                ("smalis/classes.dex/A.smali", "1"),
+                ("smalis/classes.dex/B.smali", "2"),
+                (".unpacked/res/a.xml", "3"),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        };
+        let hashes = super::source_hashes(&snapshot);
+        assert_eq!(
+            hashes.keys().collect::<Vec<_>>(),
+            [".unpacked", "smalis/classes.dex"]
+        );
+    }
+}
@@ -4,22 +4,43 @@ use std::{
     fs, io,
     path::{Path, PathBuf},
 };
-use tempfile::TempPath;
+use tempfile::TempDir;
 use tracing::instrument;
 
 use crate::{
+    cmd::{Tool, ToolError},
     deps::SMALI,
-    dir::{binarydir, temppath},
+    dir::{tempdir, temppath},
+    runtime::{phase, Jobs},
 };
 
-use super::RlaConfig;
+use super::{
+    decompiler::dex2jar_path,
+    input::{InputKind, AAR_CLASSES_JAR},
+    payload::{build_overlay, Overlay, Payload},
+    RlaConfig, OUTPUT,
+};
 
 use tracing::debug;
 
-#[instrument(skip_all, level = "debug", fields(dex=dex.file_name().unwrap().to_str().unwrap()))]
-async fn smali(smali_dir: PathBuf, dex: PathBuf, smali_jar: PathBuf) -> Result<()> {
+/// assemble a dex, it's run as a blocking job
+pub(super) fn smali(
+    smali_dir: &Path,
+    dex: &Path,
+    smali_jar: &Path,
+    options: &[String],
+) -> Result<()> {
     let tmp = temppath(dex.file_name().context("path invalid")?);
-    crate::cmd::smali(&smali_dir, &tmp, &smali_jar)?;
+    let msg = crate::cmd::smali(smali_dir, &tmp, smali_jar, options)?;
+    // smali exits with 0 on syntax errors, but the dex is not written
+    if !tmp.exists() {
+        return Err(
+            match ToolError::parse(Tool::Smali, &msg, &crate::cmd::cwd()) {
+                Some(e) => e.into(),
+                None => format_err!("smali {smali_dir:?} failed:\n{}", msg.trim()),
+            },
+        );
+    }
 
     fs::copy(tmp, dex).with_context(|| "copy error".to_string())?;
     Ok(())
@@ -41,47 +62,79 @@ fn smali_mapping_dex(smali_dir: PathBuf, dex_dir: &Path) -> Result<(PathBuf, Pat
 }
 
 #[instrument(skip_all, level = "debug")]
-async fn smalis_to_dex(root: PathBuf) -> Result<TempPath> {
-    let dex_dir = temppath("tmpdex");
-    fs::create_dir_all(&dex_dir).context("{dex_dir:? create error}")?;
+async fn smalis_to_dex(root: PathBuf, options: &[String]) -> Result<TempDir> {
+    let dex_dir = tempdir("tmpdex")?;
 
-    let smali_jar = SMALI.release_binary(binarydir())?;
+    let smali_jar = SMALI.cached()?;
 
     let smalis_dir = root.join(super::SMALIS);
-    let handles = entries(&smalis_dir)
+    let mut dexes = entries(&smalis_dir)
         .with_context(|| format!("read dir {root:?} error"))?
         .into_iter()
-        .map(|smali_dir| smali_mapping_dex(smali_dir, &dex_dir))
-        .collect::<Result<Vec<(PathBuf, PathBuf)>>>()?
-        .into_iter()
-        .map(|(smali_dir, dex)| tokio::spawn(smali(smali_dir, dex, smali_jar.clone())))
-        .collect::<Vec<_>>();
-
-    debug!("there is {} dex files", handles.len());
-    for h in handles {
-        let _r = h.await??;
+        .map(|smali_dir| smali_mapping_dex(smali_dir, dex_dir.path()))
+        .collect::<Result<Vec<(PathBuf, PathBuf)>>>()?;
+    // dex failed to disassemble(`unpack --keep-going`) are files, they are packed as they are
+    for (raw, dex) in dexes.iter().filter(|(raw, _)| raw.is_file()) {
+        debug!("{raw:?} is not disassembled, it's packed as it is");
+        fs::copy(raw, dex).with_context(|| format!("copy {raw:?} error"))?;
     }
+    dexes.retain(|(smali_dir, _)| smali_dir.is_dir());
+
+    debug!("there is {} dex files", dexes.len());
+    let jobs = Jobs::new("smali", dexes.len());
+    let tasks = dexes.into_iter().map(|(smali_dir, dex)| {
+        let (smali_jar, options) = (smali_jar.clone(), options.to_vec());
+        let name = dex
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        jobs.run(name, move || smali(&smali_dir, &dex, &smali_jar, &options))
+    });
+    futures::future::try_join_all(tasks).await?;
     Ok(dex_dir)
 }
 
-fn next_output_apk(root: &Path) -> Result<PathBuf> {
-    let out_dir = root.join("output");
-    if !out_dir.exists() {
-        fs::create_dir(&out_dir).with_context(|| format!("{out_dir:?} create error"))?;
-    }
-    let idx = walkdir::WalkDir::new(&out_dir)
+/// outputs are numbered, e.g `output/1.apk`, `output/2.apk`
+/// base apk in the output dir of split apks
+pub(crate) const BUNDLE_BASE: &str = "base.apk";
+
+/// outputs with their numbers, e.g `output/3.apk`, `output/4/`(split apks)
+pub(crate) fn outputs(root: &Path) -> Vec<(usize, PathBuf)> {
+    walkdir::WalkDir::new(root.join(OUTPUT))
+        .min_depth(1)
         .max_depth(1)
         .into_iter()
         .filter_map(|e| e.ok())
         .filter_map(|e| {
-            e.path()
+            let idx = e
+                .path()
                 .file_stem()
                 .and_then(OsStr::to_str)
-                .and_then(|s| s.parse::<usize>().ok())
+                .and_then(|s| s.parse::<usize>().ok())?;
+            Some((idx, e.path().to_path_buf()))
         })
+        .collect()
+}
+
+pub(crate) fn last_output(root: &Path) -> Option<PathBuf> {
+    outputs(root)
+        .into_iter()
+        .max_by_key(|(idx, _)| *idx)
+        .map(|(_, path)| path)
+}
+
+fn next_output(root: &Path, ext: &str) -> Result<PathBuf> {
+    let out_dir = root.join(OUTPUT);
+    if !out_dir.exists() {
+        fs::create_dir(&out_dir).with_context(|| format!("{out_dir:?} create error"))?;
+    }
+    let idx = outputs(root)
+        .into_iter()
+        .map(|(idx, _)| idx)
         .max()
         .unwrap_or(0);
-    Ok(out_dir.join(format!("{}.apk", idx + 1)))
+    Ok(out_dir.join(format!("{}.{ext}", idx + 1)))
 }
 
 fn get_dex_names(dex_dir: &Path) -> Vec<PathBuf> {
@@ -94,49 +147,245 @@ fn get_dex_names(dex_dir: &Path) -> Vec<PathBuf> {
 }
 
 #[instrument(skip_all, level = "debug")]
-async fn task_sign(apk: PathBuf) -> Result<()> {
-    crate::cmd::debugsign(&apk)
+pub(super) async fn task_sign(apk: PathBuf) -> Result<()> {
+    let name = apk.file_name().map(|n| n.to_string_lossy().to_string());
+    phase("sign", name, move || crate::cmd::debugsign(&apk)).await
+}
+
+async fn task_sync_smali_to_apk(
+    module: &Path,
+    dex_dir: Option<&Path>,
+    overlay: Option<&Overlay>,
+    apk: &Path,
+) -> Result<()> {
+    let bak_apk = module.join(super::BAK_APK);
+    fs::copy(&bak_apk, apk).with_context(|| format!("copy {bak_apk:?} to {apk:?} error"))?;
+
+    if let Some(dex_dir) = dex_dir {
+        let dex_names = get_dex_names(dex_dir);
+        crate::cmd::zip_update_files(apk, dex_dir, &dex_names)?;
+    }
+    if let Some(overlay) = overlay.filter(|o| !o.files.is_empty()) {
+        crate::cmd::zip_update_files(apk, overlay.dir.path(), &overlay.files)?;
+    }
+    Ok(())
 }
 
-async fn task_sync_smali_to_apk(root: &Path, dex_dir: &Path) -> Result<PathBuf> {
-    let next_apk = next_output_apk(root)?;
-    let bak_apk = root.join(super::BAK_APK);
-    fs::copy(&bak_apk, &next_apk)
-        .with_context(|| format!("copy {bak_apk:?} to {next_apk:?} error"))?;
+async fn task_sync_smali_full(
+    module: &Path,
+    dex_dir: Option<&Path>,
+    overlay: Option<&Overlay>,
+    apk: &Path,
+) -> Result<()> {
+    let unpacked = module.join(super::UNPACKED);
+    if let Some(overlay) = overlay {
+        for file in &overlay.files {
+            fs::copy(overlay.dir.path().join(file), unpacked.join(file)).context("copy error")?;
+        }
+    }
+    if let Some(dex_dir) = dex_dir {
+        replace_dex(&unpacked, dex_dir)?;
+    }
 
-    let dex_names = get_dex_names(dex_dir);
-    crate::cmd::zip_update_files(&next_apk, dex_dir, &dex_names)?;
-    Ok(next_apk)
+    crate::zip::zip(&unpacked, apk).context("zip error")?;
+    Ok(())
 }
 
-async fn task_sync_smali_full(root: &Path, dex_dir: &Path) -> Result<PathBuf> {
-    let unpacked = root.join(super::UNPACKED);
+/// rebuilt dex replace the unpacked ones, a dex which is not unpacked is an error
+fn replace_dex(unpacked: &Path, dex_dir: &Path) -> Result<()> {
     for dex in get_dex_names(dex_dir) {
         let origin_dex = unpacked.join(&dex);
-        if origin_dex.exists() {
+        if !origin_dex.exists() {
             return Err(format_err!("{origin_dex:?} not exists"));
         }
         fs::copy(dex_dir.join(&dex), origin_dex).context("copy error")?;
     }
-
-    let next_apk = next_output_apk(root)?;
-    crate::zip::zip(&unpacked, &next_apk).context("zip error")?;
-    Ok(next_apk)
+    Ok(())
 }
 
-pub(crate) async fn run(root: PathBuf) -> Result<()> {
-    let config =
-        fs::read_to_string(root.join(super::RLA_CONFIG)).context("rla config read error")?;
-    let config: RlaConfig = serde_json::from_str(&config).context("config parse error")?;
-    debug!("config is {config:?}");
+/// build apk of a module, which is the project root, or a split at `splits/{name}`
+async fn build_module(
+    module: &Path,
+    config: &RlaConfig,
+    payloads: &[Payload],
+    apk: &Path,
+) -> Result<()> {
+    // splits may have no dex
+    let dex_dir = if module.join(super::SMALIS).exists() {
+        Some(smalis_to_dex(module.to_path_buf(), &config.tool_options.smali).await?)
+    } else {
+        None
+    };
+    assemble_module(
+        module,
+        config.smali_only,
+        payloads,
+        dex_dir.as_ref().map(TempDir::path),
+        apk,
+    )
+    .await
+}
 
-    let dex_dir = smalis_to_dex(root.clone()).await?;
-    let apk = if config.smali_only {
-        task_sync_smali_to_apk(&root, dex_dir.as_ref()).await?
+/// build apk of a module with dex assembled in `dex_dir`, payload dex are moved out of it
+pub(super) async fn assemble_module(
+    module: &Path,
+    smali_only: bool,
+    payloads: &[Payload],
+    dex_dir: Option<&Path>,
+    apk: &Path,
+) -> Result<()> {
+    // payloads are not at the root of apk
+    let overlay = match dex_dir {
+        Some(dex_dir) if !payloads.is_empty() => Some(build_overlay(
+            &module.join(super::BAK_APK),
+            dex_dir,
+            payloads,
+        )?),
+        _ => None,
+    };
+    if smali_only {
+        task_sync_smali_to_apk(module, dex_dir, overlay.as_ref(), apk).await
     } else {
-        task_sync_smali_full(&root, dex_dir.as_ref()).await?
+        task_sync_smali_full(module, dex_dir, overlay.as_ref(), apk).await
+    }
+}
+
+/// dex file and jar file have only one dex
+async fn build_single_dex(root: &Path, options: &[String]) -> Result<(TempDir, PathBuf)> {
+    let dex_dir = smalis_to_dex(root.to_path_buf(), options).await?;
+    let dex = match get_dex_names(dex_dir.path()).as_slice() {
+        [dex] => dex_dir.path().join(dex),
+        names => return Err(format_err!("expect 1 dex, but there are {names:?}")),
     };
-    task_sign(apk).await?;
+    Ok((dex_dir, dex))
+}
+
+async fn build_dex(root: &Path, options: &[String], out: &Path) -> Result<()> {
+    let (_dex_dir, dex) = build_single_dex(root, options).await?;
+    fs::copy(&dex, out).with_context(|| format!("copy {dex:?} to {out:?} error"))?;
+    Ok(())
+}
+
+/// classes are converted from dex(dex2jar), other files are kept from the origin jar
+async fn build_jar(root: &Path, options: &[String], origin_jar: &Path, out: &Path) -> Result<()> {
+    let (_dex_dir, dex) = build_single_dex(root, options).await?;
+    let classes_jar = temppath("tmp.jar");
+    crate::cmd::dex2jar(&dex2jar_path()?, &dex, classes_jar.as_ref())?;
 
+    let tmp = tempdir("tmp.jarfiles")?;
+    let tmp = tmp.path();
+    crate::zip::unzip(
+        origin_jar,
+        tmp,
+        Some(|name: &Path| name.extension().map(|e| e != "class").unwrap_or(true)),
+    )
+    .with_context(|| format!("unzip {origin_jar:?} error"))?;
+    crate::zip::unzip(classes_jar.as_ref(), tmp, None::<fn(&Path) -> bool>)
+        .context("unzip classes error")?;
+    crate::zip::zip(tmp, out).context("zip error")?;
     Ok(())
 }
+
+/// `classes.jar` of aar is rebuilt
+async fn build_aar(root: &Path, config: &RlaConfig, out: &Path) -> Result<()> {
+    let bak_aar = InputKind::Aar.backup(root);
+    let origin = tempdir("tmp.aar")?;
+    let origin = origin.path();
+    crate::zip::unzip(
+        &bak_aar,
+        origin,
+        Some(|name: &Path| name == Path::new(AAR_CLASSES_JAR)),
+    )
+    .with_context(|| format!("unzip {bak_aar:?} error"))?;
+
+    let classes = tempdir("tmp.classes")?;
+    let classes = classes.path();
+    build_jar(
+        root,
+        &config.tool_options.smali,
+        &origin.join(AAR_CLASSES_JAR),
+        &classes.join(AAR_CLASSES_JAR),
+    )
+    .await?;
+
+    if config.smali_only {
+        fs::copy(&bak_aar, out).with_context(|| format!("copy {bak_aar:?} to {out:?} error"))?;
+        crate::cmd::zip_update_files(out, classes, &[AAR_CLASSES_JAR])?;
+    } else {
+        let unpacked = root.join(super::UNPACKED);
+        fs::copy(
+            classes.join(AAR_CLASSES_JAR),
+            unpacked.join(AAR_CLASSES_JAR),
+        )
+        .context("copy error")?;
+        crate::zip::zip(&unpacked, out).context("zip error")?;
+    }
+    Ok(())
+}
+
+/// returns the output file, or the output directory of split apks
+pub(crate) async fn run(root: PathBuf, config: RlaConfig) -> Result<PathBuf> {
+    let next_apk = next_output(&root, config.input.ext())?;
+    match config.input {
+        InputKind::Apk => {}
+        InputKind::Dex => {
+            build_dex(&root, &config.tool_options.smali, &next_apk).await?;
+            return Ok(next_apk);
+        }
+        InputKind::Jar => {
+            let origin = InputKind::Jar.backup(&root);
+            build_jar(&root, &config.tool_options.smali, &origin, &next_apk).await?;
+            return Ok(next_apk);
+        }
+        InputKind::Aar => {
+            build_aar(&root, &config, &next_apk).await?;
+            return Ok(next_apk);
+        }
+    }
+
+    if config.splits.is_empty() {
+        build_module(&root, &config, &config.payloads, &next_apk).await?;
+        task_sign(next_apk.clone()).await?;
+        return Ok(next_apk);
+    }
+
+    // `output/{N}/` holds base and split apks, which can be installed by `adb install-multiple`
+    let bundle_dir = next_apk.with_extension("");
+    fs::create_dir_all(&bundle_dir).with_context(|| format!("{bundle_dir:?} create error"))?;
+    let base = bundle_dir.join(BUNDLE_BASE);
+    build_module(&root, &config, &config.payloads, &base).await?;
+    let mut splits = vec![];
+    for name in &config.splits {
+        let apk = bundle_dir.join(format!("{name}.apk"));
+        build_module(&root.join(super::SPLITS).join(name), &config, &[], &apk).await?;
+        splits.push(apk);
+    }
+
+    // all apks must be signed by the same key
+    task_sign(base).await?;
+    for split in splits {
+        task_sign(split).await?;
+    }
+    Ok(bundle_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn test_replace_dex() {
+        let unpacked = tempfile::tempdir().unwrap();
+        let dex_dir = tempfile::tempdir().unwrap();
+        fs::write(unpacked.path().join("classes.dex"), "old").unwrap();
+        fs::write(dex_dir.path().join("classes.dex"), "new").unwrap();
+        super::replace_dex(unpacked.path(), dex_dir.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(unpacked.path().join("classes.dex")).unwrap(),
+            "new"
+        );
+
+        fs::write(dex_dir.path().join("classes2.dex"), "new").unwrap();
+        assert!(super::replace_dex(unpacked.path(), dex_dir.path()).is_err());
+    }
+}
@@ -0,0 +1,289 @@
+//! dex payloads hidden in apk (e.g `assets/`, by packers or plugin frameworks),
+//! and signatures of known packers.
+//!
+//! a payload in a nested zip has origin `{container}!{entry}`, e.g `assets/plugin.jar!classes.dex`
+
+use std::{
+    fs,
+    io::{BufReader, Cursor, Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use tracing::{info, warn};
+
+use super::input::is_dex_magic;
+use crate::{dir::tempdir, zip::read_entry};
+
+pub(super) const NESTED_SEP: char = '!';
+/// nested zip bigger than this is not scanned
+const NESTED_ZIP_LIMIT: u64 = 256 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DexFormat {
+    Dex,
+    Odex,
+    Vdex,
+    Cdex,
+}
+
+fn dex_format(magic: &[u8]) -> Option<DexFormat> {
+    if is_dex_magic(magic) {
+        Some(DexFormat::Dex)
+    } else if magic.starts_with(b"dey\n") {
+        Some(DexFormat::Odex)
+    } else if magic.starts_with(b"vdex") {
+        Some(DexFormat::Vdex)
+    } else if magic.starts_with(b"cdex") {
+        Some(DexFormat::Cdex)
+    } else {
+        None
+    }
+}
+
+/// (file name, packer)
+static PACKERS: Lazy<Vec<(Regex, &str)>> = Lazy::new(|| {
+    [
+        (r"^libjiagu.*\.so$", "360 Jiagu"),
+        (r"^libprotectClass\.so$", "360 Jiagu"),
+        (r"^lib(secexe|secmain|SecShell)\.so$", "Bangcle"),
+        (r"^libDexHelper.*\.so$", "SecNeo"),
+        (r"^libshell(a|x)?-.*\.so$", "Tencent Legu"),
+        (r"^libshell-super\..*\.so$", "Tencent Legu"),
+        (r"^lib(baiduprotect|baiduprotect_x86)\.so$", "Baidu"),
+        (r"^baiduprotect.*\.jar$", "Baidu"),
+        (r"^lib(exec|execmain)\.so$", "Ijiami"),
+        (r"^ijiami.*\.dat$", "Ijiami"),
+        (r"^lib(mobisec|sgmain).*\.so$", "Alibaba"),
+        (r"^aliprotect\.dat$", "Alibaba"),
+        (r"^libnesec\.so$", "NetEase Yidun"),
+        (r"^libdexprotector.*\.so$", "DexProtector"),
+        (r"^libAPKProtect\.so$", "APKProtect"),
+        (r"^libddog\.so$", "Naga"),
+        (r"^libchaosvmp\.so$", "Tencent"),
+    ]
+    .into_iter()
+    .map(|(re, name)| (Regex::new(re).unwrap(), name))
+    .collect()
+});
+
+pub(super) fn match_packer(entry: &str) -> Option<&'static str> {
+    let name = entry.rsplit('/').next()?;
+    PACKERS
+        .iter()
+        .find(|(re, _)| re.is_match(name))
+        .map(|(_, packer)| *packer)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Found {
+    pub origin: String,
+    pub format: DexFormat,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct ScanResult {
+    /// (packer, evidence)
+    pub packers: Vec<(&'static str, String)>,
+    pub payloads: Vec<Found>,
+}
+
+/// a disassembled payload
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Payload {
+    /// dir name at `smalis/`
+    pub smali_dir: String,
+    /// path in apk
+    pub origin: String,
+}
+
+impl Payload {
+    fn new(origin: &str) -> Self {
+        Self {
+            smali_dir: origin.replace('/', "_").replace(NESTED_SEP, "@"),
+            origin: origin.to_string(),
+        }
+    }
+}
+
+fn read_magic<R: Read>(r: &mut R) -> [u8; 8] {
+    let mut magic = [0u8; 8];
+    let _ = r.read_exact(&mut magic);
+    magic
+}
+
+fn scan_archive<R: Read + Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    prefix: Option<&str>,
+    result: &mut ScanResult,
+) -> Result<()> {
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+        let name = file.name().to_string();
+        let origin = match prefix {
+            Some(prefix) => format!("{prefix}{NESTED_SEP}{name}"),
+            None => name.clone(),
+        };
+        if let Some(packer) = match_packer(&name) {
+            result.packers.push((packer, origin.clone()));
+        }
+        // dex at root of apk is not a payload
+        if prefix.is_none() && !name.contains('/') && name.ends_with(".dex") {
+            continue;
+        }
+
+        let size = file.size();
+        let magic = read_magic(&mut file);
+        if let Some(format) = dex_format(&magic) {
+            result.payloads.push(Found { origin, format });
+        } else if prefix.is_none() && magic.starts_with(b"PK\x03\x04") && size < NESTED_ZIP_LIMIT {
+            let mut bytes = magic.to_vec();
+            file.read_to_end(&mut bytes)?;
+            if let Ok(mut nested) = zip::ZipArchive::new(Cursor::new(bytes)) {
+                scan_archive(&mut nested, Some(&origin), result)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn scan(apk: &Path) -> Result<ScanResult> {
+    let reader =
+        BufReader::new(fs::File::open(apk).with_context(|| format!("{apk:?} open error"))?);
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let mut result = ScanResult::default();
+    scan_archive(&mut archive, None, &mut result)?;
+    Ok(result)
+}
+
+pub(crate) fn report(result: &ScanResult, extract: bool) {
+    for (packer, evidence) in &result.packers {
+        warn!("suspected packer: {packer} ({evidence})");
+    }
+    for found in &result.payloads {
+        info!("found {:?} payload: {}", found.format, found.origin);
+    }
+    if !extract && result.payloads.iter().any(|f| f.format == DexFormat::Dex) {
+        info!("use `--payloads` to disassemble dex payloads");
+    }
+    for found in result
+        .payloads
+        .iter()
+        .filter(|f| f.format != DexFormat::Dex)
+    {
+        warn!(
+            "{} is {:?}, which can't be disassembled",
+            found.origin, found.format
+        );
+    }
+}
+
+/// dex payloads to disassemble
+pub(crate) fn payloads(result: &ScanResult) -> Vec<Payload> {
+    result
+        .payloads
+        .iter()
+        .filter(|f| f.format == DexFormat::Dex)
+        .map(|f| Payload::new(&f.origin))
+        .collect()
+}
+
+/// read payload bytes from apk, nested zip is supported
+pub(crate) fn read_payload(apk: &Path, origin: &str) -> Result<Vec<u8>> {
+    match origin.split_once(NESTED_SEP) {
+        None => read_entry(apk, origin),
+        Some((container, entry)) => {
+            let bytes = read_entry(apk, container)?;
+            let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+            let mut file = archive
+                .by_name(entry)
+                .with_context(|| format!("{origin} not found in {apk:?}"))?;
+            let mut bytes = vec![];
+            file.read_to_end(&mut bytes)?;
+            Ok(bytes)
+        }
+    }
+}
+
+/// files to be put back to apk, with their paths in apk
+pub(crate) struct Overlay {
+    pub dir: TempDir,
+    pub files: Vec<PathBuf>,
+}
+
+/// move payload dex out of `dex_dir`, and place them at their origin paths in overlay.
+/// containers of nested payloads are taken from `bak_apk` and updated
+pub(crate) fn build_overlay(
+    bak_apk: &Path,
+    dex_dir: &Path,
+    payloads: &[Payload],
+) -> Result<Overlay> {
+    let dir = tempdir("tmp.overlay")?;
+    let mut files = vec![];
+    for payload in payloads {
+        let dex = dex_dir.join(&payload.smali_dir);
+        if !dex.exists() {
+            warn!("payload {} is not assembled", payload.origin);
+            continue;
+        }
+        match payload.origin.split_once(NESTED_SEP) {
+            None => {
+                let dest = dir.path().join(&payload.origin);
+                if let Some(p) = dest.parent() {
+                    fs::create_dir_all(p).with_context(|| format!("{p:?} create error"))?;
+                }
+                fs::rename(&dex, &dest).context("move payload error")?;
+                files.push(PathBuf::from(&payload.origin));
+            }
+            Some((container, entry)) => {
+                let container_file = dir.path().join(container);
+                if !container_file.exists() {
+                    if let Some(p) = container_file.parent() {
+                        fs::create_dir_all(p).with_context(|| format!("{p:?} create error"))?;
+                    }
+                    fs::write(&container_file, read_entry(bak_apk, container)?)
+                        .with_context(|| format!("{container_file:?} write error"))?;
+                    files.push(PathBuf::from(container));
+                }
+                let tmp = tempdir("tmp.nested")?;
+                let dest = tmp.path().join(entry);
+                if let Some(p) = dest.parent() {
+                    fs::create_dir_all(p).with_context(|| format!("{p:?} create error"))?;
+                }
+                fs::rename(&dex, &dest).context("move payload error")?;
+                crate::cmd::zip_update_files(&container_file, tmp.path(), &[entry])?;
+            }
+        }
+    }
+    Ok(Overlay { dir, files })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_match_packer() {
+        assert_eq!(
+            super::match_packer("lib/armeabi-v7a/libjiagu.so"),
+            Some("360 Jiagu")
+        );
+        assert_eq!(
+            super::match_packer("assets/libshella-2.10.so"),
+            Some("Tencent Legu")
+        );
+        assert_eq!(super::match_packer("lib/arm64-v8a/libc++_shared.so"), None);
+    }
+
+    #[test]
+    fn test_payload_dir() {
+        let p = super::Payload::new("assets/plugin.jar!classes.dex");
+        assert_eq!(p.smali_dir, "assets_plugin.jar@classes.dex");
+    }
+}
@@ -0,0 +1,75 @@
+//! pull the base apk and split apks of an installed package from a device by adb
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Context, Result};
+use tracing::info;
+
+use super::manifest;
+use crate::cmd::Adb;
+
+/// pull apks of `package` into `dir`, the base apk is the first one
+pub(crate) fn pull_apks(adb: &Adb, package: &str, dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut remotes = adb.package_paths(package)?;
+    remotes.sort_by_key(|p| !p.ends_with("/base.apk"));
+
+    let mut apks = vec![];
+    for remote in remotes {
+        let name = remote
+            .rsplit('/')
+            .next()
+            .filter(|n| !n.is_empty())
+            .ok_or_else(|| format_err!("invalid apk path {remote}"))?;
+        let local = dir.join(name);
+        info!("pull {remote}");
+        adb.pull(&remote, &local)?;
+        if !local.exists() {
+            return Err(format_err!("{remote} is not pulled to {local:?}"));
+        }
+        apks.push(local);
+    }
+    Ok(apks)
+}
+
+/// `{package}-{versionName}`, or `{package}-{versionCode}` without a version name
+pub(crate) fn project_name(base: &Path) -> Result<String> {
+    let manifest = manifest::read(base)?;
+    let package =
+        manifest::package(&manifest).with_context(|| format!("no package in {base:?}"))?;
+    let version = manifest
+        .attr("versionName")
+        .map(|v| v.as_string())
+        .filter(|v| !v.is_empty())
+        .or_else(|| {
+            manifest
+                .attr("versionCode")
+                .and_then(|v| v.as_int())
+                .map(|c| c.to_string())
+        });
+    Ok(match version {
+        Some(version) => format!("{package}-{}", sanitize(&version)),
+        None => package,
+    })
+}
+
+/// version names may contain anything, keep them usable as a dir name
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(super::sanitize("1.2.3"), "1.2.3");
+        assert_eq!(super::sanitize("1.0 (beta/2)"), "1.0__beta_2_");
+    }
+}
@@ -1,29 +1,287 @@
 use anyhow::{format_err, Context, Result};
-use std::{fs, path::Path};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::{debug, warn};
 
-use crate::dir::temppath;
+use super::decompiler::Decompiler;
+use crate::{
+    deps::SMALI,
+    dir::{tempdir, temppath},
+};
 
-pub fn smali_to_java(path: &str) -> Result<()> {
-    let path = Path::new(path);
-    if !(path.is_file() && path.extension().map(|s| s == "smali").unwrap_or(false)) {
-        return Err(format_err!("{path:?} is not a smali file"));
+#[derive(Debug, Clone)]
+struct SmaliClass {
+    /// full path of smali file
+    path: PathBuf,
+    /// class name in smali form, e.g "com/abc/Foo$1"
+    class: String,
+}
+
+impl SmaliClass {
+    fn new(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| format!("{path:?} read error"))?;
+        let class = parse_class_name(&content)
+            .with_context(|| format!("{path:?} has no .class directive"))?;
+        Ok(Self {
+            path: path.to_owned(),
+            class,
+        })
     }
 
-    let dest = path.with_extension("java");
-    let dest_name = dest.file_name().context("invalid file name")?;
+    /// inner classes are decompiled into the java file of their outer class
+    fn outer_class(&self) -> &str {
+        outer_class(&self.class)
+    }
+}
+
+fn parse_class_name(content: &str) -> Option<String> {
+    static RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"(?m)^\s*\.class\b.*\bL(?P<class>[^;\s]+);").unwrap());
+    RE.captures(content)?
+        .name("class")
+        .map(|m| m.as_str().to_string())
+}
+
+pub(crate) fn outer_class(class: &str) -> &str {
+    let name_start = class.rfind('/').map(|i| i + 1).unwrap_or(0);
+    match class[name_start..].find('$') {
+        // `$` at the beginning is a part of name, not an inner class
+        Some(i) if i > 0 => &class[..name_start + i],
+        _ => class,
+    }
+}
+
+/// class pattern is in java form, `*` matches any characters, e.g "com.abc.*"
+fn pattern_to_regex(pattern: &str) -> Result<Regex> {
+    let re = pattern
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join(".*");
+    Regex::new(&format!("^{re}$")).with_context(|| format!("invalid class pattern {pattern:?}"))
+}
+
+fn is_smali(path: &Path) -> bool {
+    path.is_file() && path.extension().map(|s| s == "smali").unwrap_or(false)
+}
+
+/// a smali file is decompiled together with its outer and inner classes in same folder
+fn collect_files<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<SmaliClass>> {
+    let mut classes = BTreeMap::new();
+    for path in paths {
+        let path = path.as_ref();
+        let target = SmaliClass::new(path)?;
+        let parent = path.parent().context("invalid file path")?;
+        for entry in fs::read_dir(parent).with_context(|| format!("read dir {parent:?} error"))? {
+            let p = entry?.path();
+            if p == path || !is_smali(&p) || classes.contains_key(&p) {
+                continue;
+            }
+            let class = SmaliClass::new(&p)?;
+            if class.outer_class() == target.outer_class() {
+                classes.insert(p, class);
+            }
+        }
+        classes.insert(path.to_path_buf(), target);
+    }
+    Ok(classes.into_values().collect())
+}
 
-    let tmpdir = temppath("tmp.java");
-    let tmpdir: &Path = tmpdir.as_ref();
-    crate::cmd::jadx_compile_smali(path, tmpdir)?;
-    let java_file = walkdir::WalkDir::new(tmpdir)
+fn collect_dir(dir: &Path) -> Result<Vec<SmaliClass>> {
+    walkdir::WalkDir::new(dir)
         .into_iter()
         .filter_map(|e| e.ok())
-        .filter(|e| e.path().file_name().eq(&Some(dest_name)))
-        .map(|e| e.path().to_path_buf())
-        .next()
-        .with_context(|| format!(".java not found at {tmpdir:?}"))?;
-    fs::rename(&java_file, &dest)
-        .with_context(|| format!("fs rename {dest:?} to {java_file:?} error"))?;
+        .filter(|e| is_smali(e.path()))
+        .map(|e| SmaliClass::new(e.path()))
+        .collect()
+}
+
+/// assemble all smali into a dex, then decompile it at once, returns the root dir of java sources
+fn decompile(classes: &[&SmaliClass], outdir: &Path, decompiler: Decompiler) -> Result<PathBuf> {
+    let smalis = tempdir("tmp.smalis")?;
+    let smalis = smalis.path();
+    for (i, class) in classes.iter().enumerate() {
+        let dest = smalis.join(format!("{i}.smali"));
+        fs::copy(&class.path, &dest)
+            .with_context(|| format!("copy {:?} to {dest:?} error", class.path))?;
+    }
 
+    let smali_jar = SMALI.cached()?;
+    let dex = temppath("tmp.dex");
+    crate::cmd::smali(smalis, dex.as_ref(), &smali_jar, &[])?;
+
+    decompiler.decompile(dex.as_ref(), outdir)
+}
+
+/// classes are placed at `{package}/{name}.java`,
+/// but jadx moves a class without package to `defpackage`
+fn find_java(java_root: &Path, outer: &str) -> Option<PathBuf> {
+    let expected = java_root.join(format!("{outer}.java"));
+    if expected.exists() {
+        return Some(expected);
+    }
+    if outer.contains('/') {
+        return None;
+    }
+    Some(java_root.join("defpackage").join(format!("{outer}.java"))).filter(|p| p.exists())
+}
+
+type Groups<'a> = BTreeMap<&'a str, Vec<&'a SmaliClass>>;
+
+fn group_by_outer(classes: &[SmaliClass]) -> Groups<'_> {
+    let mut groups: Groups = BTreeMap::new();
+    for class in classes {
+        groups.entry(class.outer_class()).or_default().push(class);
+    }
+    groups
+}
+
+/// decompile each group into a java file, which is placed at `outdir` with package layout
+/// if provided, or next to the smali files
+fn write_java(groups: Groups, outdir: Option<&Path>, decompiler: Decompiler) -> Result<()> {
+    debug!(
+        "decompile {} classes({} java files)",
+        groups.values().map(Vec::len).sum::<usize>(),
+        groups.len()
+    );
+
+    let tmpdir = tempdir("tmp.java")?;
+    let tmpdir = tmpdir.path();
+    let java_root = decompile(
+        &groups.values().flatten().copied().collect::<Vec<_>>(),
+        tmpdir,
+        decompiler,
+    )?;
+
+    for (outer, members) in groups {
+        let java_file = match find_java(&java_root, outer) {
+            Some(f) => f,
+            None => {
+                warn!("{outer}.java not found at {tmpdir:?}");
+                continue;
+            }
+        };
+        let dest = match outdir {
+            Some(outdir) => outdir.join(format!("{outer}.java")),
+            None => {
+                let simple = outer.rsplit('/').next().unwrap_or(outer);
+                let parent = members[0].path.parent().context("invalid file path")?;
+                parent.join(format!("{simple}.java"))
+            }
+        };
+        if let Some(p) = dest.parent() {
+            fs::create_dir_all(p).with_context(|| format!("{p:?} create error"))?;
+        }
+        fs::copy(&java_file, &dest)
+            .with_context(|| format!("copy {java_file:?} to {dest:?} error"))?;
+    }
+    fs::remove_dir_all(tmpdir).ok();
     Ok(())
 }
+
+/// decompile smali files(with their outer and inner classes) to `outdir` with package layout
+pub(crate) fn smali_files_to_java<P: AsRef<Path>>(
+    files: &[P],
+    outdir: &Path,
+    decompiler: Decompiler,
+) -> Result<()> {
+    let classes = collect_files(files)?;
+    write_java(group_by_outer(&classes), Some(outdir), decompiler)
+}
+
+/// `path` can be a smali file or a directory of smali files.
+/// java files are written to `outdir` with package layout if provided, or next to the smali files.
+/// decompiler of the project is used if not provided
+pub fn smali_to_java(
+    path: &str,
+    class_pattern: Option<&str>,
+    outdir: Option<&str>,
+    decompiler: Option<Decompiler>,
+) -> Result<()> {
+    let path = Path::new(path);
+    let decompiler = match decompiler {
+        Some(d) => d,
+        None => fs::canonicalize(path)
+            .ok()
+            .and_then(|p| super::find_rla_root_from(&p))
+            .map(|root| super::RlaConfig::load(&root))
+            .transpose()?
+            .map(|config| config.decompiler)
+            .unwrap_or_default(),
+    };
+    let classes = if is_smali(path) {
+        collect_files(&[path])?
+    } else if path.is_dir() {
+        collect_dir(path)?
+    } else {
+        return Err(format_err!("{path:?} is not a smali file or directory"));
+    };
+
+    let pattern = class_pattern.map(pattern_to_regex).transpose()?;
+    let mut groups = group_by_outer(&classes);
+    if let Some(re) = &pattern {
+        groups.retain(|_, members| {
+            members
+                .iter()
+                .any(|c| re.is_match(&c.class.replace('/', ".")))
+        });
+    }
+    if groups.is_empty() {
+        return Err(format_err!("no smali class matched at {path:?}"));
+    }
+    write_java(groups, outdir.map(Path::new), decompiler)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_class_name() {
+        let content = ".class public final Lcom/abc/Foo$1;\n.super Ljava/lang/Object;\n";
+        assert_eq!(
+            super::parse_class_name(content).as_deref(),
+            Some("com/abc/Foo$1")
+        );
+    }
+
+    #[test]
+    fn test_outer_class() {
+        assert_eq!(super::outer_class("com/abc/Foo$1"), "com/abc/Foo");
+        assert_eq!(super::outer_class("com/abc/Foo$Bar$1"), "com/abc/Foo");
+        assert_eq!(super::outer_class("com/abc/Foo"), "com/abc/Foo");
+        assert_eq!(super::outer_class("com/abc/$Foo"), "com/abc/$Foo");
+        assert_eq!(super::outer_class("Foo$1"), "Foo");
+    }
+
+    #[test]
+    fn test_find_java() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        for file in [
+            "com/abc/Foo.java",
+            "com/xyz/Bar.java",
+            "defpackage/Baz.java",
+        ] {
+            let file = root.join(file);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(&file, "").unwrap();
+        }
+        let find = |outer| super::find_java(root, outer);
+        assert_eq!(find("com/abc/Foo"), Some(root.join("com/abc/Foo.java")));
+        // a class of the same name in another package is not taken
+        assert_eq!(find("com/abc/Bar"), None);
+        assert_eq!(find("Baz"), Some(root.join("defpackage/Baz.java")));
+    }
+
+    #[test]
+    fn test_pattern() {
+        let re = super::pattern_to_regex("com.abc.*").unwrap();
+        assert!(re.is_match("com.abc.Foo"));
+        assert!(re.is_match("com.abc.Foo$1"));
+        assert!(!re.is_match("com.abcd.Foo"));
+    }
+}
@@ -0,0 +1,132 @@
+//! content hashes of project files, used to find out what has been changed.
+//! snapshots are saved at `.rla/{name}.snapshot.json`
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub(crate) struct Snapshot {
+    /// relative path to project root (with `/` separator) => sha256
+    pub files: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Changes {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+fn snapshot_path(root: &Path, name: &str) -> PathBuf {
+    root.join(super::RLA_DIR)
+        .join(format!("{name}.snapshot.json"))
+}
+
+fn relative_name(root: &Path, path: &Path) -> Result<String> {
+    let name = path
+        .strip_prefix(root)
+        .with_context(|| format!("{path:?} not at dir {root:?}"))?;
+    Ok(name
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+impl Snapshot {
+    /// hash all files under `dirs`(relative to `root`), missing dirs are skipped
+    pub fn scan(root: &Path, dirs: &[&str]) -> Result<Self> {
+        let mut files = BTreeMap::new();
+        for dir in dirs {
+            let dir = root.join(dir);
+            if !dir.exists() {
+                continue;
+            }
+            for entry in walkdir::WalkDir::new(&dir) {
+                let entry = entry.context("dir entry error")?;
+                if !entry.file_type().is_file() {
+                    continue;
+                }
+                let hash = crate::hash::sha256_file(entry.path())?;
+                files.insert(relative_name(root, entry.path())?, hash);
+            }
+        }
+        Ok(Self { files })
+    }
+
+    pub fn load(root: &Path, name: &str) -> Result<Option<Self>> {
+        let path = snapshot_path(root, name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path).with_context(|| format!("{path:?} read error"))?;
+        let snapshot =
+            serde_json::from_str(&content).with_context(|| format!("{path:?} parse error"))?;
+        Ok(Some(snapshot))
+    }
+
+    pub fn save(&self, root: &Path, name: &str) -> Result<()> {
+        let path = snapshot_path(root, name);
+        if let Some(p) = path.parent() {
+            fs::create_dir_all(p).with_context(|| format!("{p:?} create error"))?;
+        }
+        fs::write(&path, serde_json::to_string(self)?)
+            .with_context(|| format!("{path:?} write error"))
+    }
+
+    /// changes from `self` to `current`
+    pub fn changes(&self, current: &Snapshot) -> Changes {
+        let mut changes = Changes::default();
+        for (name, hash) in &current.files {
+            match self.files.get(name) {
+                None => changes.added.push(name.clone()),
+                Some(old) if old != hash => changes.modified.push(name.clone()),
+                _ => {}
+            }
+        }
+        changes.removed = self
+            .files
+            .keys()
+            .filter(|name| !current.files.contains_key(*name))
+            .cloned()
+            .collect();
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Snapshot;
+
+    #[test]
+    fn test_changes() {
+        let old = Snapshot {
+            files: [("a", "1"), ("b", "2"), ("c", "3")]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        };
+        let new = Snapshot {
+            files: [("a", "1"), ("b", "x"), ("d", "4")]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        };
+        let changes = old.changes(&new);
+        assert_eq!(changes.added, vec!["d"]);
+        assert_eq!(changes.modified, vec!["b"]);
+        assert_eq!(changes.removed, vec!["c"]);
+    }
+}
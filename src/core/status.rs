@@ -0,0 +1,233 @@
+//! what has been changed in a project since unpack, and since the last pack.
+//! it works on snapshots rather than git, so it works for projects without git
+
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Result;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::progress::outln;
+
+use super::{
+    pack::last_output,
+    payload::{Payload, NESTED_SEP},
+    snapshot::{Changes, Snapshot},
+    RlaConfig, SMALIS, SPLITS, UNPACKED,
+};
+
+/// snapshot taken when the project is unpacked
+pub(crate) const UNPACK_SNAPSHOT: &str = "unpack";
+/// snapshot taken when the last output is packed
+pub(crate) const PACK_SNAPSHOT: &str = "pack";
+
+/// dirs that pack builds from
+const SOURCE_DIRS: &[&str] = &[SMALIS, UNPACKED, SPLITS];
+
+/// changes in a dex dir(classes) or an unpacked dir(files)
+#[derive(Debug, Default, Serialize)]
+struct Group {
+    dir: String,
+    added: Vec<String>,
+    removed: Vec<String>,
+    modified: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+    since_unpack: Vec<Group>,
+    last_output: Option<String>,
+    /// changes since the last output, `None` if it's never packed
+    pending: Option<Vec<Group>>,
+}
+
+/// pack rewrites dex and payload containers at `.unpacked` in full mode, they are not sources
+fn is_build_product(name: &str, payloads: &[Payload]) -> bool {
+    let (dir, item) = group_of(name);
+    if !dir.ends_with(UNPACKED) {
+        return false;
+    }
+    let is_root_dex = !item.contains('/') && item.ends_with(".dex");
+    is_root_dex
+        || (dir == UNPACKED
+            && payloads
+                .iter()
+                .any(|p| p.origin.split(NESTED_SEP).next() == Some(item.as_str())))
+}
+
+fn sources(mut snapshot: Snapshot, config: &RlaConfig) -> Snapshot {
+    snapshot
+        .files
+        .retain(|name, _| !is_build_product(name, &config.payloads));
+    snapshot
+}
+
+fn scan(root: &Path, config: &RlaConfig) -> Result<Snapshot> {
+    Ok(sources(Snapshot::scan(root, SOURCE_DIRS)?, config))
+}
+
+/// snapshots saved before build products were left out still have them
+fn load(root: &Path, name: &str, config: &RlaConfig) -> Result<Option<Snapshot>> {
+    Ok(Snapshot::load(root, name)?.map(|s| sources(s, config)))
+}
+
+pub(crate) fn save_snapshot(root: &Path, config: &RlaConfig, name: &str) -> Result<Snapshot> {
+    let snapshot = scan(root, config)?;
+    snapshot.save(root, name)?;
+    Ok(snapshot)
+}
+
+/// true if it's never packed, or sources are changed since the last pack
+pub(crate) fn needs_pack(root: &Path, config: &RlaConfig) -> Result<bool> {
+    if last_output(root).is_none() {
+        return Ok(true);
+    }
+    let packed = match load(root, PACK_SNAPSHOT, config)? {
+        Some(s) => s,
+        None => return Ok(true),
+    };
+    Ok(!packed.changes(&scan(root, config)?).is_empty())
+}
+
+/// `smalis/classes.dex/com/abc/Foo.smali` => (`smalis/classes.dex`, `com/abc/Foo`),
+/// `splits/a/.unpacked/res/a.xml` => (`splits/a/.unpacked`, `res/a.xml`)
+pub(super) fn group_of(name: &str) -> (String, String) {
+    let parts = name.split('/').collect::<Vec<_>>();
+    for (i, part) in parts.iter().enumerate() {
+        let end = match *part {
+            SMALIS if i + 2 < parts.len() => i + 2,
+            UNPACKED if i + 1 < parts.len() => i + 1,
+            _ => continue,
+        };
+        let item = parts[end..].join("/");
+        let item = match *part {
+            SMALIS => item
+                .strip_suffix(".smali")
+                .map(str::to_string)
+                .unwrap_or(item),
+            _ => item,
+        };
+        return (parts[..end].join("/"), item);
+    }
+    match name.rsplit_once('/') {
+        Some((dir, file)) => (dir.to_string(), file.to_string()),
+        None => (String::new(), name.to_string()),
+    }
+}
+
+fn group(changes: Changes) -> Vec<Group> {
+    let mut groups: BTreeMap<String, Group> = BTreeMap::new();
+    let mut add = |name: &str, f: fn(&mut Group) -> &mut Vec<String>| {
+        let (dir, item) = group_of(name);
+        let group = groups.entry(dir.clone()).or_insert_with(|| Group {
+            dir,
+            ..Default::default()
+        });
+        f(group).push(item);
+    };
+    changes.added.iter().for_each(|n| add(n, |g| &mut g.added));
+    changes
+        .removed
+        .iter()
+        .for_each(|n| add(n, |g| &mut g.removed));
+    changes
+        .modified
+        .iter()
+        .for_each(|n| add(n, |g| &mut g.modified));
+    groups.into_values().collect()
+}
+
+fn print_groups(groups: &[Group]) {
+    if groups.is_empty() {
+        outln!("  (no changes)");
+    }
+    for g in groups {
+        outln!("  {}/", g.dir);
+        g.modified.iter().for_each(|i| outln!("    modified: {i}"));
+        g.added.iter().for_each(|i| outln!("    added:    {i}"));
+        g.removed.iter().for_each(|i| outln!("    removed:  {i}"));
+    }
+}
+
+pub(crate) fn run(root: &Path, config: &RlaConfig, json: bool) -> Result<()> {
+    let current = scan(root, config)?;
+    let unpacked = match load(root, UNPACK_SNAPSHOT, config)? {
+        Some(s) => s,
+        None => {
+            warn!("no unpack snapshot, record current files as the unpacked state");
+            current.save(root, UNPACK_SNAPSHOT)?;
+            current.clone()
+        }
+    };
+
+    let last_output = last_output(root).and_then(|p| {
+        p.strip_prefix(root)
+            .ok()
+            .map(|p| p.to_string_lossy().to_string())
+    });
+    let status = Status {
+        since_unpack: group(unpacked.changes(&current)),
+        pending: load(root, PACK_SNAPSHOT, config)?.map(|s| group(s.changes(&current))),
+        last_output,
+    };
+
+    if json {
+        crate::progress::print_json(&status)?;
+        return Ok(());
+    }
+    outln!("changes since unpack:");
+    print_groups(&status.since_unpack);
+    match (&status.last_output, &status.pending) {
+        (Some(output), Some(pending)) => {
+            outln!("changes since {output}:");
+            print_groups(pending);
+        }
+        (Some(output), None) => outln!("no snapshot of {output}, pack again to track changes"),
+        _ => outln!("not packed yet"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{group_of, is_build_product};
+    use crate::core::payload::Payload;
+
+    #[test]
+    fn test_group_of() {
+        let g = |dir: &str, item: &str| (dir.to_string(), item.to_string());
+        assert_eq!(
+            group_of("smalis/classes.dex/com/abc/Foo.smali"),
+            g("smalis/classes.dex", "com/abc/Foo")
+        );
+        assert_eq!(
+            group_of("splits/a/smalis/classes.dex/A.smali"),
+            g("splits/a/smalis/classes.dex", "A")
+        );
+        assert_eq!(
+            group_of(".unpacked/res/layout/a.xml"),
+            g(".unpacked", "res/layout/a.xml")
+        );
+        assert_eq!(group_of("splits/a/bak.apk"), g("splits/a", "bak.apk"));
+    }
+
+    #[test]
+    fn test_is_build_product() {
+        let payloads = [Payload {
+            smali_dir: "assets_a.zip@b.dex".to_string(),
+            origin: "assets/a.zip!b.dex".to_string(),
+        }];
+        assert!(is_build_product(".unpacked/classes2.dex", &payloads));
+        assert!(is_build_product(
+            "splits/a/.unpacked/classes.dex",
+            &payloads
+        ));
+        assert!(is_build_product(".unpacked/assets/a.zip", &payloads));
+        assert!(!is_build_product(".unpacked/assets/b.dex", &payloads));
+        assert!(!is_build_product(
+            ".unpacked/AndroidManifest.xml",
+            &payloads
+        ));
+        assert!(!is_build_product("smalis/classes.dex/A.smali", &payloads));
+    }
+}
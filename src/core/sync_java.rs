@@ -0,0 +1,165 @@
+//! keep `jadx-src` up to date with edited smali, only changed classes are decompiled again
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{format_err, Result};
+use tracing::{debug, error, info, warn};
+
+use super::{
+    smali_to_java::{outer_class, smali_files_to_java},
+    snapshot::Snapshot,
+    RlaConfig, JADX_SRC, SMALIS, SPLITS,
+};
+
+/// snapshot of smalis which `jadx-src` is generated from
+pub(crate) const SNAPSHOT: &str = "jadx";
+
+/// `jadx -e` exports a gradle project, java files are not at the root,
+/// other decompilers place java files at the root
+fn java_root(jadx_src: &Path) -> PathBuf {
+    ["app/src/main/java", "src/main/java", "sources"]
+        .iter()
+        .map(|p| jadx_src.join(p))
+        .find(|p| p.is_dir())
+        .unwrap_or_else(|| jadx_src.to_path_buf())
+}
+
+/// `smalis/classes.dex/com/abc/Foo$1.smali` => `com/abc/Foo$1`
+fn class_of(name: &str) -> Option<&str> {
+    let name = name.strip_prefix(SMALIS)?.strip_prefix('/')?;
+    let (_dex, class) = name.split_once('/')?;
+    class.strip_suffix(".smali")
+}
+
+/// `splits/{name}/smalis/classes.dex/com/abc/Foo.smali`
+fn is_split_smali(name: &str) -> bool {
+    name.strip_prefix(SPLITS)
+        .and_then(|n| n.strip_prefix('/'))
+        .and_then(|n| n.split_once('/'))
+        .and_then(|(_, n)| class_of(n))
+        .is_some()
+}
+
+/// smali files of the project and its splits
+fn scan(root: &Path) -> Result<Snapshot> {
+    let mut snapshot = Snapshot::scan(root, &[SMALIS, SPLITS])?;
+    snapshot
+        .files
+        .retain(|name, _| class_of(name).is_some() || is_split_smali(name));
+    Ok(snapshot)
+}
+
+pub(crate) fn save_snapshot(root: &Path) -> Result<()> {
+    scan(root)?.save(root, SNAPSHOT)
+}
+
+/// `jadx-src` is only generated when jadx is enabled
+pub(crate) fn check(config: &RlaConfig) -> Result<()> {
+    if config.jadx_enable {
+        Ok(())
+    } else {
+        Err(format_err!(
+            "the project is unpacked with --no-jadx, there is no {JADX_SRC} to sync"
+        ))
+    }
+}
+
+pub(crate) fn run(root: &Path, config: &RlaConfig) -> Result<()> {
+    check(config)?;
+    let current = scan(root)?;
+    let last = match Snapshot::load(root, SNAPSHOT)? {
+        Some(s) => s,
+        None => {
+            warn!("no smali snapshot for {JADX_SRC:?}, assume it's up to date");
+            return current.save(root, SNAPSHOT);
+        }
+    };
+    let changes = last.changes(&current);
+
+    // jadx-src is decompiled from the base apk, snapshots before splits were scanned have none
+    if last.files.keys().any(|n| is_split_smali(n)) {
+        let skipped = changes
+            .added
+            .iter()
+            .chain(&changes.modified)
+            .chain(&changes.removed)
+            .filter(|n| is_split_smali(n))
+            .count();
+        if skipped > 0 {
+            warn!("{skipped} changed smali of splits are skipped, {JADX_SRC} is of the base apk");
+        }
+    }
+
+    // decompile changed classes, and the outer classes of removed inner classes
+    let mut files = changes
+        .added
+        .iter()
+        .chain(&changes.modified)
+        .filter(|name| class_of(name).is_some())
+        .map(|name| root.join(name))
+        .collect::<BTreeSet<_>>();
+    let java_root = java_root(&root.join(JADX_SRC));
+    for class in changes.removed.iter().filter_map(|name| class_of(name)) {
+        let outer = outer_class(class);
+        match current.files.keys().find(|k| class_of(k) == Some(outer)) {
+            Some(name) => {
+                files.insert(root.join(name));
+            }
+            None => {
+                let java = java_root.join(format!("{outer}.java"));
+                debug!("remove {java:?}");
+                fs::remove_file(&java).ok();
+            }
+        }
+    }
+
+    if !files.is_empty() {
+        smali_files_to_java(
+            &files.into_iter().collect::<Vec<_>>(),
+            &java_root,
+            config.decompiler,
+        )?;
+    }
+    current.save(root, SNAPSHOT)?;
+    info!(
+        "{JADX_SRC} synced: {} added, {} modified, {} removed",
+        changes.added.len(),
+        changes.modified.len(),
+        changes.removed.len()
+    );
+
+    if config.git_enable && !changes.is_empty() {
+        if let Err(e) = crate::cmd::git_add(root)
+            .and_then(|_| crate::cmd::git_commit(root, "Sync java from smali"))
+        {
+            error!("{e:?}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_class_of() {
+        assert_eq!(
+            super::class_of("smalis/classes.dex/com/abc/Foo$1.smali"),
+            Some("com/abc/Foo$1")
+        );
+        assert_eq!(super::class_of("smalis/classes.dex/Foo.smali"), Some("Foo"));
+        assert_eq!(super::class_of(".unpacked/classes.dex"), None);
+    }
+
+    #[test]
+    fn test_is_split_smali() {
+        assert!(super::is_split_smali(
+            "splits/a/smalis/classes.dex/Foo.smali"
+        ));
+        assert!(!super::is_split_smali("splits/a/.unpacked/res/a.xml"));
+        assert!(!super::is_split_smali("smalis/classes.dex/Foo.smali"));
+    }
+}
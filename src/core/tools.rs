@@ -0,0 +1,59 @@
+//! tools used by rla, embedded or local ones given by `RLA_{KEY}` env vars or `tools` of config
+
+use anyhow::{format_err, Result};
+
+use crate::{
+    deps::{Source, ALL, EXTERNAL},
+    progress::outln,
+};
+
+/// tools are run with `--version` to make sure they work
+pub(crate) fn list() -> Result<()> {
+    let mut failed = 0;
+    outln!("{:<16} {:<22} {:<24} path", "tool", "source", "version");
+    for dep in ALL {
+        let (path, source) = match dep.local() {
+            Some((path, source)) => (path.to_string_lossy().to_string(), source),
+            None => ("-".to_string(), Source::Embedded),
+        };
+        // files other than tools have no version
+        let version = match dep.version.map(|_| dep.check()) {
+            None => "-".to_string(),
+            Some(Ok(version)) => match (&source, dep.version) {
+                (Source::Embedded, Some(embedded)) => embedded.to_string(),
+                _ => version,
+            },
+            Some(Err(e)) => {
+                failed += 1;
+                let msg = e.root_cause().to_string();
+                format!("error: {}", msg.lines().next().unwrap_or_default())
+            }
+        };
+        let source = source.to_string();
+        outln!("{:<16} {source:<22} {version:<24} {path}", dep.key);
+    }
+    // external tools are only checked for existence, they have no common way to report version
+    for tool in EXTERNAL {
+        let (path, source) = match tool.local() {
+            Some((path, source)) => (path.to_string_lossy().to_string(), source.to_string()),
+            None => ("-".to_string(), "-".to_string()),
+        };
+        let version = match tool.local().map(|_| tool.path()) {
+            Some(Err(e)) => {
+                failed += 1;
+                format!("error: {}", e.root_cause())
+            }
+            _ => "-".to_string(),
+        };
+        outln!("{:<16} {source:<22} {version:<24} {path}", tool.key);
+    }
+    outln!(
+        "\nreplace a tool by env var(e.g `{}=/path/to/smali.jar`), or by `tools` of {}",
+        crate::deps::SMALI.env_var(),
+        super::RLA_CONFIG
+    );
+    if failed > 0 {
+        return Err(format_err!("{failed} tools can't run"));
+    }
+    Ok(())
+}
@@ -6,25 +6,60 @@ use std::{
 
 use anyhow::{format_err, Context, Result};
 use tokio::spawn;
-use tracing::{error, instrument};
+use tracing::{debug, error, info, instrument, warn};
 
 use crate::{
-    deps::{BAKSMALI, FRIDA_INDEX_JS, FRIDA_PACKAGE, GIT_IGNORE},
-    dir::{binarydir, temppath},
+    deps::{BAKSMALI, DX, FRIDA_INDEX_JS, FRIDA_PACKAGE, GIT_IGNORE},
+    dir::tempdir,
+    runtime::{phase, Jobs},
 };
 
-use super::{RlaConfig, RLA_CONFIG};
+use super::{
+    bundle::Bundle,
+    decompiler::Decompiler,
+    input::{InputKind, AAR_CLASSES_JAR},
+    payload::{self, read_payload, Payload},
+    RlaConfig, RLA_CONFIG,
+};
+
+/// a dex(or payload) failed to disassemble with `--keep-going`
+struct Failed {
+    /// e.g `classes3.dex`, `splits/{name}/classes2.dex`
+    name: String,
+    error: anyhow::Error,
+    /// the raw dex kept in the project
+    kept: Option<PathBuf>,
+}
+
+/// dex disassembled, and the ones failed with `--keep-going`
+#[derive(Default)]
+struct Disassembled {
+    done: Vec<String>,
+    failed: Vec<Failed>,
+}
+
+impl Disassembled {
+    fn extend(&mut self, other: Disassembled) {
+        self.done.extend(other.done);
+        self.failed.extend(other.failed);
+    }
+}
+
+fn save_config(outdir: &Path, config: &RlaConfig) -> Result<()> {
+    // currently , we don't have any config, just use a file to identifier the project root dir
+    fs::write(
+        outdir.join(RLA_CONFIG),
+        serde_json::to_string_pretty(config)?,
+    )
+    .context("rla config write error")
+}
 
 #[instrument(skip_all, level = "debug")]
 async fn task_prepare_files(outdir: PathBuf, apk: PathBuf, config: RlaConfig) -> Result<()> {
-    let bak = outdir.join(super::BAK_APK);
+    let bak = config.input.backup(&outdir);
     fs::copy(&apk, &bak)?;
     GIT_IGNORE.release_binary(&outdir)?;
-    // currently , we don't have any config, just use a file to identifier the project root dir
-    fs::write(
-        outdir.join(RLA_CONFIG),
-        serde_json::to_string_pretty(&config)?,
-    )?;
+    save_config(&outdir, &config)?;
 
     // prepare mini firda
     let mini_frida = outdir.join(super::MINI_FRIDA);
@@ -34,19 +69,42 @@ async fn task_prepare_files(outdir: PathBuf, apk: PathBuf, config: RlaConfig) ->
     Ok(())
 }
 
-#[instrument(skip_all, level = "debug", fields(dex=dex.file_name().unwrap().to_str().unwrap()))]
-async fn task_baksmali(dex: PathBuf, smalis_dir: PathBuf, baksmali_jar: PathBuf) -> Result<String> {
+/// disassemble a dex to `{smalis_dir}/{dex name}`, it's run as a blocking job
+fn baksmali(
+    dex: &Path,
+    smalis_dir: &Path,
+    baksmali_jar: &Path,
+    options: &[String],
+) -> Result<String> {
     let dexname = dex.file_name().context("dex file no name")?;
     let outdir = smalis_dir.join(dexname);
-    crate::cmd::baksmali(&dex, &outdir, &baksmali_jar)
+    crate::cmd::baksmali(dex, &outdir, baksmali_jar, options)
+}
+
+/// a dex failed to disassemble is copied to `{smalis_dir}/{dex name}`, pack uses it as it is
+fn keep_raw_dex(dex: &Path, smalis_dir: &Path) -> Result<PathBuf> {
+    let raw = smalis_dir.join(dex.file_name().context("dex file no name")?);
+    if raw.is_dir() {
+        fs::remove_dir_all(&raw).with_context(|| format!("remove {raw:?} error"))?;
+    }
+    fs::copy(dex, &raw).with_context(|| format!("copy {dex:?} to {raw:?} error"))?;
+    Ok(raw)
 }
 
-async fn task_dex_to_smali(dex_dir: &Path, outdir: &Path) -> Result<()> {
+/// `options` are extra options of baksmali. all dex are waited,
+/// a failed one is kept raw with `keep_going`, or it fails the task
+async fn task_dex_to_smali(
+    dex_dir: &Path,
+    outdir: &Path,
+    options: &[String],
+    keep_going: bool,
+) -> Result<Disassembled> {
     let dexes = walkdir::WalkDir::new(dex_dir)
         .max_depth(1)
         .into_iter()
         .filter_map(|e| e.ok())
         .map(|e| e.path().to_path_buf())
+        .filter(|p| p.is_file() && p.extension().eq(&Some(OsStr::new("dex"))))
         .collect::<Vec<_>>();
     if dexes.is_empty() {
         return Err(format_err!("no dex found"))?;
@@ -54,34 +112,77 @@ async fn task_dex_to_smali(dex_dir: &Path, outdir: &Path) -> Result<()> {
 
     let smalis = outdir.join(super::SMALIS);
     fs::create_dir(&smalis).with_context(|| format!("{smalis:?} create error"))?;
-    let baksmali_jar = BAKSMALI.release_binary(binarydir())?;
-    let handles = dexes
-        .into_iter()
-        .filter(|p| p.is_file() && p.extension().eq(&Some(OsStr::new("dex"))))
-        .map(|dex| tokio::spawn(task_baksmali(dex, smalis.clone(), baksmali_jar.clone())))
-        .collect::<Vec<_>>();
-    for h in handles {
-        h.await??;
+    let baksmali_jar = BAKSMALI.cached()?;
+    let jobs = Jobs::new("baksmali", dexes.len());
+    let tasks = dexes.into_iter().map(|dex| {
+        let (smalis, baksmali_jar, options) =
+            (smalis.clone(), baksmali_jar.clone(), options.to_vec());
+        let name = job_name(outdir, &dex);
+        let job = jobs.run(name.clone(), move || {
+            baksmali(&dex, &smalis, &baksmali_jar, &options)
+        });
+        async move { (name, job.await) }
+    });
+    let mut disassembled = Disassembled::default();
+    for (name, result) in futures::future::join_all(tasks).await {
+        match result {
+            Ok(_) => disassembled.done.push(name),
+            Err(error) if keep_going => {
+                let dex = dex_dir.join(Path::new(&name).file_name().unwrap_or_default());
+                let kept = Some(keep_raw_dex(&dex, &smalis)?);
+                disassembled.failed.push(Failed { name, error, kept });
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(disassembled)
+}
+
+fn file_name(path: &Path) -> Option<String> {
+    path.file_name().map(|n| n.to_string_lossy().to_string())
+}
+
+/// `classes2.dex`, or `splits/{name}/classes2.dex` for a split
+fn job_name(outdir: &Path, dex: &Path) -> String {
+    let name = dex.file_name().unwrap_or_default().to_string_lossy();
+    let split = outdir
+        .parent()
+        .filter(|p| p.file_name() == Some(OsStr::new(super::SPLITS)))
+        .and_then(|_| outdir.file_name());
+    match split {
+        Some(split) => format!("{}/{}/{name}", super::SPLITS, split.to_string_lossy()),
+        None => name.to_string(),
     }
-    Ok(())
 }
 
 #[instrument(skip_all, level = "debug")]
-async fn task_extract_all(outdir: PathBuf, apk: PathBuf) -> Result<()> {
+async fn task_extract_all(
+    outdir: PathBuf,
+    apk: PathBuf,
+    options: Vec<String>,
+    keep_going: bool,
+) -> Result<Disassembled> {
     let unpacked = outdir.join(super::UNPACKED);
-    crate::cmd::unzip(&apk, &unpacked)?;
-
-    task_dex_to_smali(unpacked.as_ref(), &outdir).await?;
+    let dir = unpacked.clone();
+    phase("unzip", file_name(&apk), move || {
+        crate::cmd::unzip(&apk, &dir)
+    })
+    .await?;
 
-    Ok(())
+    task_dex_to_smali(unpacked.as_ref(), &outdir, &options, keep_going).await
 }
 
 #[instrument(skip_all, level = "debug")]
-async fn task_extract_smali(outdir: PathBuf, apk: PathBuf) -> Result<()> {
-    let temp_dexs = temppath("tmpdex");
+async fn task_extract_smali(
+    outdir: PathBuf,
+    apk: PathBuf,
+    options: Vec<String>,
+    keep_going: bool,
+) -> Result<Disassembled> {
+    let temp_dexs = tempdir("tmpdex")?;
     crate::zip::unzip(
         &apk,
-        &temp_dexs,
+        temp_dexs.path(),
         Some(|name: &Path| {
             name.parent().map(|s| s.as_os_str() == "").unwrap_or(true)
                 && name.extension().map(|s| s == "dex").unwrap_or(false)
@@ -89,14 +190,159 @@ async fn task_extract_smali(outdir: PathBuf, apk: PathBuf) -> Result<()> {
     )
     .context("unzip error")?;
 
-    task_dex_to_smali(temp_dexs.as_ref(), &outdir).await?;
+    task_dex_to_smali(temp_dexs.path(), &outdir, &options, keep_going).await
+}
 
-    Ok(())
+/// dex file is disassembled to `smalis/classes.dex`, whatever its file name is
+#[instrument(skip_all, level = "debug")]
+async fn task_extract_dex(
+    outdir: PathBuf,
+    dex: PathBuf,
+    options: Vec<String>,
+    keep_going: bool,
+) -> Result<Disassembled> {
+    let temp_dexs = tempdir("tmpdex")?;
+    fs::copy(&dex, temp_dexs.path().join("classes.dex")).context("copy dex error")?;
+
+    task_dex_to_smali(temp_dexs.path(), &outdir, &options, keep_going).await
+}
+
+/// jar is converted to dex(dx) first
+#[instrument(skip_all, level = "debug")]
+async fn task_extract_jar(
+    outdir: PathBuf,
+    jar: PathBuf,
+    options: Vec<String>,
+    keep_going: bool,
+) -> Result<Disassembled> {
+    let temp_dexs = tempdir("tmpdex")?;
+    let dx_jar = DX.cached()?;
+    let dex = temp_dexs.path().join("classes.dex");
+    let name = file_name(&jar);
+    phase("dx", name, move || {
+        crate::cmd::dx_to_dex(&[&jar], &dx_jar, &dex)
+    })
+    .await?;
+
+    task_dex_to_smali(temp_dexs.path(), &outdir, &options, keep_going).await
+}
+
+/// classes of aar are at `classes.jar`
+#[instrument(skip_all, level = "debug")]
+async fn task_extract_aar(
+    outdir: PathBuf,
+    aar: PathBuf,
+    smali_only: bool,
+    options: Vec<String>,
+    keep_going: bool,
+) -> Result<Disassembled> {
+    if !smali_only {
+        let (aar, unpacked) = (aar.clone(), outdir.join(super::UNPACKED));
+        phase("unzip", file_name(&aar), move || {
+            crate::cmd::unzip(&aar, &unpacked)
+        })
+        .await?;
+    }
+    let tmp = tempdir("tmp.aar")?;
+    crate::zip::unzip(
+        &aar,
+        tmp.path(),
+        Some(|name: &Path| name == Path::new(AAR_CLASSES_JAR)),
+    )
+    .context("unzip error")?;
+
+    let jar = tmp.path().join(AAR_CLASSES_JAR);
+    task_extract_jar(outdir, jar, options, keep_going).await
+}
+
+/// a split is unpacked like the base apk, but only dex and files are extracted
+#[instrument(skip_all, level = "debug", fields(split=name.as_str()))]
+async fn task_extract_split(
+    outdir: PathBuf,
+    name: String,
+    apk: PathBuf,
+    smali_only: bool,
+    options: Vec<String>,
+    keep_going: bool,
+) -> Result<Disassembled> {
+    let splitdir = outdir.join(super::SPLITS).join(&name);
+    fs::create_dir_all(&splitdir).with_context(|| format!("{splitdir:?} create error"))?;
+    fs::copy(&apk, splitdir.join(super::BAK_APK))?;
+
+    // config splits(abi, density, language) usually have no dex
+    let has_dex = !crate::zip::dex_names(&apk)?.is_empty();
+    if smali_only {
+        if has_dex {
+            return task_extract_smali(splitdir, apk, options, keep_going).await;
+        }
+    } else {
+        let unpacked = splitdir.join(super::UNPACKED);
+        let dir = unpacked.clone();
+        phase("unzip", file_name(&apk), move || {
+            crate::cmd::unzip(&apk, &dir)
+        })
+        .await?;
+        if has_dex {
+            return task_dex_to_smali(&unpacked, &splitdir, &options, keep_going).await;
+        }
+    }
+    Ok(Disassembled::default())
+}
+
+/// payloads are disassembled into `smalis/{payload.smali_dir}`, the ones disassembled are returned.
+/// a failed payload is left in the apk with `keep_going`
+#[instrument(skip_all, level = "debug")]
+async fn task_extract_payloads(
+    outdir: PathBuf,
+    apk: PathBuf,
+    payloads: Vec<Payload>,
+    options: Vec<String>,
+    keep_going: bool,
+) -> Result<(Vec<Payload>, Disassembled)> {
+    let temp_dexs = tempdir("tmpdex")?;
+    let smalis = outdir.join(super::SMALIS);
+    fs::create_dir_all(&smalis).with_context(|| format!("{smalis:?} create error"))?;
+    let baksmali_jar = BAKSMALI.cached()?;
+    let mut extracted = vec![];
+    let mut disassembled = Disassembled::default();
+    for payload in payloads {
+        let dex = temp_dexs.path().join(&payload.smali_dir);
+        fs::write(&dex, read_payload(&apk, &payload.origin)?)
+            .with_context(|| format!("{dex:?} write error"))?;
+        let (smalis_dir, baksmali_jar, options) =
+            (smalis.clone(), baksmali_jar.clone(), options.clone());
+        let name = Some(payload.smali_dir.clone());
+        let result = phase("baksmali", name, move || {
+            baksmali(&dex, &smalis_dir, &baksmali_jar, &options)
+        })
+        .await;
+        let name = format!("payload {}", payload.origin);
+        match result {
+            Ok(_) => {
+                disassembled.done.push(name);
+                extracted.push(payload);
+            }
+            Err(error) if keep_going => {
+                let partial = smalis.join(&payload.smali_dir);
+                if partial.exists() {
+                    fs::remove_dir_all(&partial)
+                        .with_context(|| format!("remove {partial:?} error"))?;
+                }
+                disassembled.failed.push(Failed {
+                    name,
+                    error,
+                    kept: None,
+                });
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((extracted, disassembled))
 }
 
 #[instrument(skip_all, level = "debug")]
 async fn task_git_init(outdir: PathBuf) -> Result<()> {
-    if let Err(e) = crate::cmd::git_init(&outdir) {
+    if let Err(e) = phase("git-init", None, move || crate::cmd::git_init(&outdir)).await {
         // Notice user that git is not available, but not fail the procedure
         error!("{e:?}");
     }
@@ -104,8 +350,14 @@ async fn task_git_init(outdir: PathBuf) -> Result<()> {
 }
 
 #[instrument(skip_all, level = "debug")]
-async fn task_jadx_reverse(outdir: PathBuf, apk: PathBuf) -> Result<()> {
-    if let Err(e) = crate::cmd::jadx_extract_src(&apk, &outdir.join(super::JADX_SRC)) {
+async fn task_jadx_reverse(
+    outdir: PathBuf,
+    apk: PathBuf,
+    kind: InputKind,
+    decompiler: Decompiler,
+) -> Result<()> {
+    let export = move || decompiler.export(&apk, kind, &outdir.join(super::JADX_SRC));
+    if let Err(e) = phase("decompile", Some(decompiler.to_string()), export).await {
         error!("{e:?}");
     }
     Ok(())
@@ -113,21 +365,89 @@ async fn task_jadx_reverse(outdir: PathBuf, apk: PathBuf) -> Result<()> {
 
 #[instrument(skip_all, level = "debug")]
 async fn task_git_commit(outdir: PathBuf, msg: String) {
-    if let Err(e) = crate::cmd::git_add(&outdir).and_then(|_| crate::cmd::git_commit(&outdir, &msg))
-    {
+    let commit =
+        move || crate::cmd::git_add(&outdir).and_then(|_| crate::cmd::git_commit(&outdir, &msg));
+    if let Err(e) = phase("git-commit", None, commit).await {
         error!("{e:?}");
     }
 }
 
-pub(crate) async fn run(outdir: PathBuf, apk: PathBuf, config: RlaConfig) -> Result<()> {
+/// the first line of the exception, e.g `java.lang.ArrayIndexOutOfBoundsException: ...`
+fn reason(e: &anyhow::Error) -> String {
+    let msg = format!("{e:#}");
+    let lines = msg
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>();
+    lines
+        .iter()
+        .find(|l| l.contains("Exception") || l.contains("Error:"))
+        .map(|l| l.trim_start_matches("Exception in thread \"main\" "))
+        .or_else(|| lines.last().copied())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// summary of `--keep-going`, paths are relative to the project
+fn report(outdir: &Path, disassembled: &Disassembled) {
+    let total = disassembled.done.len() + disassembled.failed.len();
+    info!(
+        "{} of {total} disassembled: {}",
+        disassembled.done.len(),
+        disassembled.done.join(", ")
+    );
+    for f in &disassembled.failed {
+        debug!("{} failed: {:?}", f.name, f.error);
+        let path = f
+            .kept
+            .as_deref()
+            .and_then(|p| p.strip_prefix(outdir).ok())
+            .unwrap_or_else(|| Path::new(&f.name));
+        warn!(
+            path = %path.display(),
+            "{} failed: {}",
+            f.name,
+            reason(&f.error)
+        );
+    }
+    if disassembled
+        .failed
+        .iter()
+        .any(|f| !f.name.starts_with("payload "))
+    {
+        warn!(
+            "failed dex are kept at `smalis/{{dex}}` as they are, pack puts their original bytes back"
+        );
+    }
+    if disassembled
+        .failed
+        .iter()
+        .any(|f| f.name.starts_with("payload "))
+    {
+        warn!("failed payloads are left in the apk as they are");
+    }
+}
+
+pub(crate) async fn run(outdir: PathBuf, bundle: Bundle, mut config: RlaConfig) -> Result<()> {
     // >> base.apk
     // >> unzip >> smali
     // >> git init
     // >> jadx
     // >> .gitginore, .rla.config.json
+    // >> splits/{name} (if there are split apks)
     // ====
     // >> git commit
 
+    let apk = bundle.base;
+    if config.input == InputKind::Apk {
+        let scan = payload::scan(&apk)?;
+        payload::report(&scan, config.payload_enable);
+        if config.payload_enable {
+            config.payloads = payload::payloads(&scan);
+        }
+    }
+
     // parallel tasks begin
     let mut handles = vec![spawn(task_prepare_files(
         outdir.clone(),
@@ -135,22 +455,92 @@ pub(crate) async fn run(outdir: PathBuf, apk: PathBuf, config: RlaConfig) -> Res
         config.clone(),
     ))];
 
-    if config.smali_only {
-        handles.push(spawn(task_extract_smali(outdir.clone(), apk.clone())));
-    } else {
-        handles.push(spawn(task_extract_all(outdir.clone(), apk.clone())));
+    let options = config.tool_options.baksmali.clone();
+    let (out, input, keep_going) = (outdir.clone(), apk.clone(), config.keep_going);
+    let mut extracts = vec![match config.input {
+        InputKind::Apk if config.smali_only => {
+            spawn(task_extract_smali(out, input, options, keep_going))
+        }
+        InputKind::Apk => spawn(task_extract_all(out, input, options, keep_going)),
+        InputKind::Dex => spawn(task_extract_dex(out, input, options, keep_going)),
+        InputKind::Jar => spawn(task_extract_jar(out, input, options, keep_going)),
+        InputKind::Aar => spawn(task_extract_aar(
+            out,
+            input,
+            config.smali_only,
+            options,
+            keep_going,
+        )),
+    }];
+
+    for (name, split) in bundle.splits {
+        extracts.push(spawn(task_extract_split(
+            outdir.clone(),
+            name,
+            split,
+            config.smali_only,
+            config.tool_options.baksmali.clone(),
+            keep_going,
+        )));
     }
 
     if config.git_enable {
         handles.push(spawn(task_git_init(outdir.clone())));
     }
     if config.jadx_enable {
-        handles.push(spawn(task_jadx_reverse(outdir.clone(), apk.clone())));
+        handles.push(spawn(task_jadx_reverse(
+            outdir.clone(),
+            apk.clone(),
+            config.input,
+            config.decompiler,
+        )));
+    }
+    // all tasks are finished before an error is returned, none is left writing the project
+    let mut disassembled = Disassembled::default();
+    let mut first_error = None;
+    for h in extracts {
+        match h.await? {
+            Ok(d) => disassembled.extend(d),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
     }
     for h in handles {
-        h.await??;
+        if let Err(e) = h.await? {
+            first_error.get_or_insert(e);
+        }
+    }
+    if let Some(e) = first_error {
+        return Err(e);
+    }
+    if !config.payloads.is_empty() {
+        let (payloads, d) = task_extract_payloads(
+            outdir.clone(),
+            apk.clone(),
+            config.payloads.clone(),
+            config.tool_options.baksmali.clone(),
+            keep_going,
+        )
+        .await?;
+        config.payloads = payloads;
+        disassembled.extend(d);
+    }
+    if keep_going {
+        config.raw_dex = disassembled
+            .failed
+            .iter()
+            .filter(|f| !f.name.starts_with("payload "))
+            .map(|f| f.name.clone())
+            .collect();
+        save_config(&outdir, &config)?;
+        report(&outdir, &disassembled);
     }
 
+    if config.jadx_enable {
+        super::sync_java::save_snapshot(&outdir)?;
+    }
+    super::status::save_snapshot(&outdir, &config, super::status::UNPACK_SNAPSHOT)?;
     if config.git_enable {
         task_git_commit(outdir, "Frist init project".to_string()).await;
     }
@@ -0,0 +1,440 @@
+//! `pack --watch`: on each change of the project, rebuild dex of changed smali dirs only,
+//! re-assemble and sign the changed apks at `output/watch.apk`(or `output/watch/`),
+//! and install them if a device is given.
+//! files are polled by modification time, dex are cached for the whole session
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{format_err, Context, Result};
+use chrono::Local;
+use tempfile::TempDir;
+use tracing::{debug, info, warn};
+
+use super::{
+    launch, manifest,
+    pack::{assemble_module, smali, task_sign, BUNDLE_BASE},
+    payload::{Payload, NESTED_SEP},
+    InputKind, PackConfig, RlaConfig, OUTPUT, SMALIS, SPLITS, UNPACKED,
+};
+use crate::{
+    cmd::Adb,
+    deps::SMALI,
+    dir::tempdir,
+    progress::is_json,
+    runtime::{rt, Jobs},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+/// a build starts after files stop changing for this long
+const DEBOUNCE: Duration = Duration::from_millis(500);
+/// `output/watch.apk`, not numbered so it's not in the history
+const WATCH_OUTPUT: &str = "watch";
+
+/// modification time and size of files
+type Stamps = BTreeMap<PathBuf, (SystemTime, u64)>;
+
+/// the project root, or a split at `splits/{name}`
+struct Module {
+    dir: PathBuf,
+    /// `None` for the base module
+    split: Option<String>,
+    payloads: Vec<Payload>,
+}
+
+impl Module {
+    /// files written into `.unpacked` by pack, they are not sources
+    fn is_build_product(&self, unpacked_name: &Path) -> bool {
+        let is_root_dex = unpacked_name.parent() == Some(Path::new(""))
+            && unpacked_name.extension() == Some(OsStr::new("dex"));
+        is_root_dex
+            || self.payloads.iter().any(|p| {
+                let container = p.origin.split(NESTED_SEP).next().unwrap_or_default();
+                unpacked_name == Path::new(container)
+            })
+    }
+}
+
+/// what to rebuild
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Dirty {
+    /// (module index, dex name)
+    dex: BTreeSet<(usize, String)>,
+    /// modules to re-assemble
+    modules: BTreeSet<usize>,
+}
+
+impl Dirty {
+    fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+}
+
+fn modules(root: &Path, config: &RlaConfig) -> Vec<Module> {
+    let mut modules = vec![Module {
+        dir: root.to_path_buf(),
+        split: None,
+        payloads: config.payloads.clone(),
+    }];
+    modules.extend(config.splits.iter().map(|name| Module {
+        dir: root.join(SPLITS).join(name),
+        split: Some(name.clone()),
+        payloads: vec![],
+    }));
+    modules
+}
+
+fn scan(modules: &[Module]) -> Stamps {
+    let mut stamps = Stamps::new();
+    for module in modules {
+        for dir in [SMALIS, UNPACKED] {
+            let dir = module.dir.join(dir);
+            for entry in walkdir::WalkDir::new(&dir)
+                .into_iter()
+                .filter_map(|e| e.ok())
+            {
+                let path = entry.path();
+                if !entry.file_type().is_file()
+                    || (dir.ends_with(UNPACKED)
+                        && module.is_build_product(path.strip_prefix(&dir).unwrap_or(path)))
+                {
+                    continue;
+                }
+                // files may be removed while scanning
+                if let Ok(meta) = entry.metadata() {
+                    let mtime = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    stamps.insert(path.to_path_buf(), (mtime, meta.len()));
+                }
+            }
+        }
+    }
+    stamps
+}
+
+/// the module of a changed file, the deepest one wins as splits are inside the root
+fn classify(modules: &[Module], path: &Path, dirty: &mut Dirty) {
+    let found = modules
+        .iter()
+        .enumerate()
+        .filter_map(|(i, m)| path.strip_prefix(&m.dir).ok().map(|rel| (i, rel)))
+        .min_by_key(|(_, rel)| rel.components().count());
+    let (idx, rel) = match found {
+        Some(f) => f,
+        None => return,
+    };
+    dirty.modules.insert(idx);
+    if let Ok(rel) = rel.strip_prefix(SMALIS) {
+        if let Some(dex) = rel.components().next() {
+            let dex = dex.as_os_str().to_string_lossy().to_string();
+            dirty.dex.insert((idx, dex));
+        }
+    }
+}
+
+fn changes(modules: &[Module], old: &Stamps, new: &Stamps) -> Dirty {
+    let mut dirty = Dirty::default();
+    for (path, stamp) in new {
+        if old.get(path) != Some(stamp) {
+            classify(modules, path, &mut dirty);
+        }
+    }
+    for path in old.keys().filter(|p| !new.contains_key(*p)) {
+        classify(modules, path, &mut dirty);
+    }
+    dirty
+}
+
+/// wait for changes, and then for files to be stable
+fn wait_changes(modules: &[Module], stamps: &mut Stamps) -> Dirty {
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let mut current = scan(modules);
+        if &current == stamps {
+            continue;
+        }
+        loop {
+            thread::sleep(DEBOUNCE);
+            let next = scan(modules);
+            if next == current {
+                break;
+            }
+            current = next;
+        }
+        let dirty = changes(modules, stamps, &current);
+        *stamps = current;
+        if !dirty.is_empty() {
+            return dirty;
+        }
+    }
+}
+
+struct Watcher<'a> {
+    root: &'a Path,
+    config: &'a RlaConfig,
+    modules: Vec<Module>,
+    /// dex of each module, at `{cache}/{module index}/{dex name}`
+    cache: TempDir,
+    adb: Option<(Adb, bool)>,
+    package: Option<String>,
+}
+
+impl Watcher<'_> {
+    fn dex_cache(&self, idx: usize) -> PathBuf {
+        self.cache.path().join(idx.to_string())
+    }
+
+    fn apk_path(&self, module: &Module) -> PathBuf {
+        let out = self.root.join(OUTPUT);
+        match (&module.split, self.config.splits.is_empty()) {
+            (None, true) => out.join(format!("{WATCH_OUTPUT}.apk")),
+            (None, false) => out.join(WATCH_OUTPUT).join(BUNDLE_BASE),
+            (Some(name), _) => out.join(WATCH_OUTPUT).join(format!("{name}.apk")),
+        }
+    }
+
+    async fn build_dex(&self, dirty: &Dirty) -> Result<()> {
+        let smali_jar = SMALI.cached()?;
+        let jobs = Jobs::new("smali", dirty.dex.len());
+        let mut tasks = vec![];
+        for (idx, name) in &dirty.dex {
+            let smali_dir = self.modules[*idx].dir.join(SMALIS).join(name);
+            let cache = self.dex_cache(*idx);
+            fs::create_dir_all(&cache).with_context(|| format!("{cache:?} create error"))?;
+            let dex = cache.join(name);
+            if smali_dir.is_dir() {
+                let (smali_jar, options) =
+                    (smali_jar.clone(), self.config.tool_options.smali.clone());
+                let task = jobs.run(name.clone(), move || {
+                    smali(&smali_dir, &dex, &smali_jar, &options)
+                });
+                let name = name.clone();
+                tasks
+                    .push(async move { task.await.with_context(|| format!("build {name} error")) });
+            } else if smali_dir.is_file() {
+                // not disassembled by `unpack --keep-going`
+                fs::copy(&smali_dir, &dex).with_context(|| format!("copy {smali_dir:?} error"))?;
+            } else {
+                fs::remove_file(&dex).ok();
+            }
+        }
+        futures::future::try_join_all(tasks).await?;
+        Ok(())
+    }
+
+    async fn assemble(&self, idx: usize) -> Result<()> {
+        let module = &self.modules[idx];
+        let apk = self.apk_path(module);
+        if let Some(parent) = apk.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("{parent:?} create error"))?;
+        }
+        // payloads are moved out of the dex dir, so assemble from a copy of cached dex
+        let dex_dir = if module.dir.join(SMALIS).exists() {
+            // a fresh dir for each module and cycle, or dex of other modules are packed in
+            let tmp = tempdir("tmp.watchdex")?;
+            let cache = self.dex_cache(idx);
+            fs::create_dir_all(&cache).with_context(|| format!("{cache:?} create error"))?;
+            for entry in fs::read_dir(&cache)? {
+                let dex = entry?.path();
+                fs::copy(&dex, tmp.path().join(dex.file_name().unwrap_or_default()))
+                    .with_context(|| format!("copy {dex:?} error"))?;
+            }
+            Some(tmp)
+        } else {
+            None
+        };
+        assemble_module(
+            &module.dir,
+            self.config.smali_only,
+            &module.payloads,
+            dex_dir.as_ref().map(TempDir::path),
+            &apk,
+        )
+        .await?;
+        task_sign(apk).await
+    }
+
+    fn install(&mut self) -> Result<()> {
+        let (adb, yes) = match &self.adb {
+            Some(adb) => adb,
+            None => return Ok(()),
+        };
+        let base = self.apk_path(&self.modules[0]);
+        let apks = match base.parent() {
+            Some(dir) if !self.config.splits.is_empty() => launch::output_apks(dir)?,
+            _ => vec![base],
+        };
+        if self.package.is_none() {
+            let manifest = manifest::read(&apks[0])?;
+            self.package = manifest::package(&manifest);
+        }
+        let package = self.package.as_deref().context("no package in manifest")?;
+        launch::install(adb, package, &apks, *yes)
+    }
+
+    fn cycle(&mut self, dirty: &Dirty) -> Result<String> {
+        rt().block_on(async {
+            self.build_dex(dirty).await?;
+            for idx in &dirty.modules {
+                self.assemble(*idx).await?;
+            }
+            Ok::<_, anyhow::Error>(())
+        })?;
+        self.install()?;
+
+        let mut summary = dirty
+            .dex
+            .iter()
+            .map(|(idx, name)| match &self.modules[*idx].split {
+                Some(split) => format!("{split}/{name}"),
+                None => name.clone(),
+            })
+            .collect::<Vec<_>>();
+        if summary.is_empty() {
+            summary.push("resources".to_string());
+        }
+        let output = if self.config.splits.is_empty() {
+            format!("{OUTPUT}/{WATCH_OUTPUT}.apk")
+        } else {
+            format!("{OUTPUT}/{WATCH_OUTPUT}/")
+        };
+        let installed = if self.adb.is_some() {
+            ", installed"
+        } else {
+            ""
+        };
+        Ok(format!("{} -> {output}{installed}", summary.join(" ")))
+    }
+}
+
+fn print_result(result: Result<String>, start: Instant) {
+    let time = Local::now().format("%H:%M:%S");
+    let elapsed = start.elapsed().as_secs_f64();
+    // json events have their own times
+    match result {
+        Ok(summary) if is_json() => info!("ok {elapsed:.1}s  {summary}"),
+        Err(e) if is_json() => warn!("failed {elapsed:.1}s  {e:#}"),
+        Ok(summary) => println!("[{time}] ok {elapsed:.1}s  {summary}"),
+        Err(e) => println!("[{time}] failed {elapsed:.1}s  {e:#}"),
+    }
+}
+
+pub(crate) fn run(root: &Path, config: &RlaConfig, pack_config: &PackConfig) -> Result<()> {
+    if config.input != InputKind::Apk {
+        return Err(format_err!(
+            "watch is only for apk projects, not {}",
+            config.input
+        ));
+    }
+    let adb = match &pack_config.install {
+        Some(c) => Some((Adb::new(c.adb.clone(), c.serial.clone())?, c.yes)),
+        None => None,
+    };
+    // each rebuild runs smali and apksigner in the same jvms
+    crate::cmd::enable_jvm_worker();
+    let cache = tempdir("tmp.watch")?;
+    let mut watcher = Watcher {
+        root,
+        config,
+        modules: modules(root, config),
+        cache,
+        adb,
+        package: None,
+    };
+
+    // the first build is a full build
+    let mut stamps = scan(&watcher.modules);
+    let mut dirty = Dirty::default();
+    stamps
+        .keys()
+        .for_each(|p| classify(&watcher.modules, p, &mut dirty));
+    dirty.modules.extend(0..watcher.modules.len());
+
+    info!("watching {root:?}, press Ctrl-C to stop");
+    loop {
+        debug!("rebuild {dirty:?}");
+        let start = Instant::now();
+        let result = watcher.cycle(&dirty);
+        // a failed dex is rebuilt with the next change
+        if result.is_err() {
+            for (idx, name) in &dirty.dex {
+                fs::remove_file(watcher.dex_cache(*idx).join(name)).ok();
+            }
+        }
+        print_result(result, start);
+        let next = wait_changes(&watcher.modules, &mut stamps);
+        // dex failed in the last cycle are built again
+        dirty = Dirty {
+            dex: next
+                .dex
+                .into_iter()
+                .chain(missing_dex(&watcher, &stamps))
+                .collect(),
+            modules: next.modules,
+        };
+    }
+}
+
+/// dex which have smali but not in the cache, e.g failed to build
+fn missing_dex(watcher: &Watcher, stamps: &Stamps) -> BTreeSet<(usize, String)> {
+    let mut all = Dirty::default();
+    stamps
+        .keys()
+        .for_each(|p| classify(&watcher.modules, p, &mut all));
+    all.dex
+        .into_iter()
+        .filter(|(idx, name)| !watcher.dex_cache(*idx).join(name).exists())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{classify, Dirty, Module};
+
+    #[test]
+    fn test_classify() {
+        let module = |dir: &str, split: Option<&str>| Module {
+            dir: PathBuf::from(dir),
+            split: split.map(str::to_string),
+            payloads: vec![],
+        };
+        let modules = [module("/p", None), module("/p/splits/a", Some("a"))];
+        let mut dirty = Dirty::default();
+        classify(
+            &modules,
+            Path::new("/p/smalis/classes2.dex/A.smali"),
+            &mut dirty,
+        );
+        classify(
+            &modules,
+            Path::new("/p/splits/a/.unpacked/res/a.xml"),
+            &mut dirty,
+        );
+        assert_eq!(
+            dirty,
+            Dirty {
+                dex: [(0, "classes2.dex".to_string())].into_iter().collect(),
+                modules: [0, 1].into_iter().collect(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_build_product() {
+        let module = Module {
+            dir: PathBuf::from("/p"),
+            split: None,
+            payloads: vec![],
+        };
+        assert!(module.is_build_product(Path::new("classes.dex")));
+        assert!(!module.is_build_product(Path::new("assets/a.dex")));
+        assert!(!module.is_build_product(Path::new("res/a.xml")));
+    }
+}
@@ -1,64 +1,271 @@
-use anyhow::{Context, Result};
+use anyhow::{format_err, Context, Result};
+use once_cell::sync::Lazy;
 use std::{
+    collections::{BTreeMap, BTreeSet},
     fs,
     io::{BufWriter, Write},
     path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
 };
+use tracing::{debug, info, warn};
+
+use crate::dir::{binarydir, cachedir};
 
 /// copied from android sdk tools(30.0.3)
 pub const APK_SIGNER: &Dep = &Dep {
+    key: "apksigner",
     name: "apksigner.jar",
     bytes: include_bytes!("./apksigner.jar"),
+    version: Some("30.0.3"),
 };
 
 /// copied from android sdk tools(30.0.3)
 pub const DX: &Dep = &Dep {
+    key: "dx",
     name: "dx.jar",
     bytes: include_bytes!("./dx.jar"),
+    version: Some("30.0.3"),
 };
 
 /// android studio debug sign,
 pub const DEBUG_STORE: &Dep = &Dep {
+    key: "debug-keystore",
     name: "debug.keystore",
     bytes: include_bytes!("./debug.keystore"),
+    version: None,
 };
 
 /// smali
 /// https://bitbucket.org/JesusFreke/smali/downloads/
 pub const SMALI: &Dep = &Dep {
+    key: "smali",
     name: "smali",
     bytes: include_bytes!("./smali-2.5.2.jar"),
+    version: Some("2.5.2"),
 };
 
 /// baksmali
 /// https://bitbucket.org/JesusFreke/smali/downloads/
 pub const BAKSMALI: &Dep = &Dep {
+    key: "baksmali",
     name: "baksmali",
     bytes: include_bytes!("./baksmali-2.5.2.jar"),
+    version: Some("2.5.2"),
+};
+
+/// persistent jvm running the jars above, built from `worker/RlaWorker.java`
+pub const JVM_WORKER: &Dep = &Dep {
+    key: "jvm-worker",
+    name: "rla-worker.jar",
+    bytes: include_bytes!("./rla-worker.jar"),
+    version: Some("1.0.0"),
 };
 
 pub const GIT_IGNORE: &Dep = &Dep {
+    key: "gitignore",
     name: ".gitignore",
     bytes: include_bytes!("./gitignore"),
+    version: None,
 };
 
 pub const FRIDA_INDEX_JS: &Dep = &Dep {
+    key: "frida-index",
     name: "index.js",
     bytes: include_bytes!("./index.js"),
+    version: None,
 };
 
 pub const FRIDA_PACKAGE: &Dep = &Dep {
+    key: "frida-package",
     name: "package.json",
     bytes: include_bytes!("./package.json"),
+    version: None,
 };
 
+/// all deps, which can be overridden by `RLA_{KEY}` or `tools` of project config
+pub const ALL: &[&Dep] = &[
+    APK_SIGNER,
+    DX,
+    DEBUG_STORE,
+    SMALI,
+    BAKSMALI,
+    JVM_WORKER,
+    GIT_IGNORE,
+    FRIDA_INDEX_JS,
+    FRIDA_PACKAGE,
+];
+
+/// cfr decompiler jar, https://github.com/leibnitz27/cfr
+pub const CFR: &Tool = &Tool { key: "cfr" };
+
+/// procyon decompiler jar, https://github.com/mstrobel/procyon
+pub const PROCYON: &Tool = &Tool { key: "procyon" };
+
+/// vineflower decompiler jar, https://github.com/Vineflower/vineflower
+pub const VINEFLOWER: &Tool = &Tool { key: "vineflower" };
+
+/// `decompile.py` of krakatau(v1), https://github.com/Storyyeller/Krakatau
+pub const KRAKATAU: &Tool = &Tool { key: "krakatau" };
+
+/// `d2j-dex2jar` script, https://github.com/pxb1988/dex2jar
+pub const DEX2JAR: &Tool = &Tool { key: "dex2jar" };
+
+/// tools which are not embedded, they are only given by `RLA_{KEY}` or `tools` of project config
+pub const EXTERNAL: &[&Tool] = &[CFR, PROCYON, VINEFLOWER, KRAKATAU, DEX2JAR];
+
+/// whether `key` names a dep or an external tool
+pub fn is_known(key: &str) -> bool {
+    ALL.iter().any(|d| d.key == key) || EXTERNAL.iter().any(|t| t.key == key)
+}
+
+/// cached files verified in this process
+static VERIFIED: Lazy<Mutex<BTreeSet<PathBuf>>> = Lazy::new(Default::default);
+/// key => sha256 of the embedded file
+static HASHES: Lazy<Mutex<BTreeMap<&'static str, String>>> = Lazy::new(Default::default);
+
+/// key => path, set from the project config
+static OVERRIDES: Lazy<RwLock<BTreeMap<String, PathBuf>>> = Lazy::new(Default::default);
+
+/// where a dep is taken from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Embedded,
+    /// name of the env var
+    Env(String),
+    Config,
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Embedded => write!(f, "embedded"),
+            Source::Env(var) => write!(f, "env {var}"),
+            Source::Config => write!(f, "config"),
+        }
+    }
+}
+
+/// remove the cache of embedded files
+pub fn clean_cache() -> Result<()> {
+    let cache = match cachedir() {
+        Some(dir) if dir.exists() => dir,
+        _ => {
+            info!("no cache");
+            return Ok(());
+        }
+    };
+    let size = walkdir::WalkDir::new(&cache)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum::<u64>();
+    fs::remove_dir_all(&cache).with_context(|| format!("remove {cache:?} error"))?;
+    info!("removed {cache:?}, {:.1} MB", size as f64 / 1024.0 / 1024.0);
+    Ok(())
+}
+
+/// use local files instead of embedded ones, e.g `{"smali": "/opt/smali-3.0.3.jar"}`
+pub fn set_overrides(tools: BTreeMap<String, PathBuf>) {
+    if let Ok(mut overrides) = OVERRIDES.write() {
+        *overrides = tools;
+    }
+}
+
 pub struct Dep {
+    /// used in `RLA_{KEY}` env var(e.g `RLA_DEBUG_KEYSTORE`) and `tools` of config
+    pub key: &'static str,
     pub name: &'static str,
     pub bytes: &'static [u8],
+    /// version of the tool, `None` for other files
+    pub version: Option<&'static str>,
+}
+
+fn env_var(key: &str) -> String {
+    format!("RLA_{}", key.to_uppercase().replace('-', "_"))
+}
+
+/// path given by `RLA_{KEY}` or `tools` of config, env var goes first
+fn local(key: &str) -> Option<(PathBuf, Source)> {
+    let var = env_var(key);
+    if let Some(path) = std::env::var_os(&var).filter(|p| !p.is_empty()) {
+        return Some((PathBuf::from(path), Source::Env(var)));
+    }
+    let overrides = OVERRIDES.read().ok()?;
+    overrides
+        .get(key)
+        .map(|path| (path.clone(), Source::Config))
+}
+
+pub struct Tool {
+    /// used in `RLA_{KEY}` env var(e.g `RLA_CFR`) and `tools` of config
+    pub key: &'static str,
+}
+
+impl Tool {
+    pub fn env_var(&self) -> String {
+        env_var(self.key)
+    }
+
+    pub fn local(&self) -> Option<(PathBuf, Source)> {
+        local(self.key)
+    }
+
+    /// the configured file, it's an error if it's not given or not found
+    pub fn path(&self) -> Result<PathBuf> {
+        let (path, source) = self.local().with_context(|| {
+            format!(
+                "{} is not configured, set `{}` or `tools` of project config",
+                self.key,
+                self.env_var()
+            )
+        })?;
+        if !path.exists() {
+            return Err(format_err!(
+                "{} from {source} not found: {path:?}",
+                self.key
+            ));
+        }
+        Ok(path)
+    }
 }
 
 impl Dep {
+    pub fn env_var(&self) -> String {
+        env_var(self.key)
+    }
+
+    /// a local file replacing the embedded one, env var goes first
+    pub fn local(&self) -> Option<(PathBuf, Source)> {
+        local(self.key)
+    }
+
+    /// local tools are used in place, other local files are copied to `dir`
+    fn release_local(&self, dir: &Path) -> Result<Option<PathBuf>> {
+        let (path, source) = match self.local() {
+            Some(local) => local,
+            None => return Ok(None),
+        };
+        if !path.is_file() {
+            return Err(format_err!(
+                "{} from {source} not found: {path:?}",
+                self.key
+            ));
+        }
+        if self.version.is_some() {
+            return Ok(Some(path));
+        }
+        let file = dir.join(self.name);
+        fs::copy(&path, &file).with_context(|| format!("copy {path:?} error"))?;
+        Ok(Some(file))
+    }
+
+    /// the embedded file written to `dir`, or the local file if it's given
     pub fn release_binary(&self, dir: &Path) -> Result<PathBuf> {
+        if let Some(path) = self.release_local(dir)? {
+            return Ok(path);
+        }
         let file = dir.join(self.name);
         let mut writer = BufWriter::new(fs::File::create(&file)?);
         writer
@@ -66,4 +273,111 @@ impl Dep {
             .context("release binary error")?;
         Ok(file)
     }
+
+    /// the embedded file in the persistent cache, it's extracted once and verified once a process.
+    /// the local file if it's given
+    pub fn cached(&self) -> Result<PathBuf> {
+        if let Some(path) = self.release_local(binarydir())? {
+            return Ok(path);
+        }
+        let cache = match cachedir() {
+            Some(dir) => dir,
+            None => return self.release_binary(binarydir()),
+        };
+        let file = cache.join(&self.hash()[..16]).join(self.name);
+        let mut verified = VERIFIED
+            .lock()
+            .map_err(|_| format_err!("cache lock error"))?;
+        if verified.contains(&file) {
+            return Ok(file);
+        }
+        match self.cache_in(&cache) {
+            Ok(file) => {
+                verified.insert(file.clone());
+                Ok(file)
+            }
+            Err(e) => {
+                warn!("{e:?}, cache is not used");
+                self.release_binary(binarydir())
+            }
+        }
+    }
+
+    fn hash(&self) -> String {
+        let mut hashes = HASHES.lock().unwrap_or_else(|e| e.into_inner());
+        hashes
+            .entry(self.key)
+            .or_insert_with(|| crate::hash::sha256(self.bytes))
+            .clone()
+    }
+
+    /// `{cache}/{hash}/{name}`, a broken file is replaced.
+    /// files are written to a temp file and renamed, so concurrent processes see complete files only
+    fn cache_in(&self, cache: &Path) -> Result<PathBuf> {
+        let hash = self.hash();
+        let dir = cache.join(&hash[..16]);
+        let file = dir.join(self.name);
+        if file.is_file() && crate::hash::sha256_file(&file)? == hash {
+            return Ok(file);
+        }
+        fs::create_dir_all(&dir).with_context(|| format!("{dir:?} create error"))?;
+        let mut tmp = tempfile::NamedTempFile::new_in(&dir)
+            .with_context(|| format!("temp file create error at {dir:?}"))?;
+        tmp.write_all(self.bytes)
+            .and_then(|_| tmp.flush())
+            .context("release binary error")?;
+        tmp.persist(&file)
+            .with_context(|| format!("{file:?} write error"))?;
+        debug!("{} is extracted to {file:?}", self.key);
+        Ok(file)
+    }
+
+    /// run the tool with `--version`, returns the first line of output
+    pub fn check(&self) -> Result<String> {
+        let path = self.cached()?;
+        let mut c = crate::cmd::jar_command(&path)?;
+        c.arg("--version");
+        let out = crate::cmd::run(c)?;
+        Ok(out
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty())
+            .unwrap_or_default()
+            .to_string())
+    }
+
+    /// version of the embedded tool, or the one reported by the local tool
+    pub fn tool_version(&self) -> Option<String> {
+        match self.local() {
+            Some(_) => self.check().ok(),
+            None => self.version.map(str::to_string),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::Dep;
+
+    const DEP: &Dep = &Dep {
+        key: "test",
+        name: "test.jar",
+        bytes: b"test content",
+        version: None,
+    };
+
+    #[test]
+    fn test_cache_in() {
+        let cache = tempfile::tempdir().unwrap();
+        let file = DEP.cache_in(cache.path()).unwrap();
+        assert_eq!(fs::read(&file).unwrap(), DEP.bytes);
+        assert!(file.starts_with(cache.path().join(&DEP.hash()[..16])));
+
+        // broken file is replaced
+        fs::write(&file, b"broken").unwrap();
+        assert_eq!(DEP.cache_in(cache.path()).unwrap(), file);
+        assert_eq!(fs::read(&file).unwrap(), DEP.bytes);
+    }
 }
@@ -0,0 +1,173 @@
+// persistent jvm of rla, it runs main classes of jars without starting a jvm for each run.
+//
+// build rla-worker.jar:
+//   javac --release 8 -d /tmp/rla-worker RlaWorker.java
+//   jar --create --file ../rla-worker.jar --main-class RlaWorker -C /tmp/rla-worker .
+//
+// protocol on stdin/stdout, strings are utf-8 and ended by '\0':
+//   ready:    "ready"
+//   request:  count, jar, args...
+//   response: exit status, stdout length, stdout bytes, stderr length, stderr bytes
+
+import java.io.BufferedInputStream;
+import java.io.BufferedOutputStream;
+import java.io.ByteArrayOutputStream;
+import java.io.File;
+import java.io.FileDescriptor;
+import java.io.FileInputStream;
+import java.io.FileOutputStream;
+import java.io.IOException;
+import java.io.InputStream;
+import java.io.OutputStream;
+import java.io.PrintStream;
+import java.lang.reflect.InvocationTargetException;
+import java.lang.reflect.Method;
+import java.net.URL;
+import java.net.URLClassLoader;
+import java.nio.charset.StandardCharsets;
+import java.security.Permission;
+import java.util.Arrays;
+import java.util.HashMap;
+import java.util.Map;
+import java.util.jar.JarFile;
+
+public class RlaWorker {
+    static final String VERSION = "1.0.0";
+
+    /** thrown instead of exiting the jvm */
+    static class Exit extends SecurityException {
+        final int status;
+
+        Exit(int status) {
+            super("exit " + status);
+            this.status = status;
+        }
+    }
+
+    /** the first exit of a run, tools may catch the exit and exit again(e.g dx) */
+    static Integer firstExit;
+
+    /** main method of each jar */
+    static final Map<String, Method> MAINS = new HashMap<>();
+
+    public static void main(String[] args) throws Exception {
+        if (args.length > 0 && args[0].equals("--version")) {
+            System.out.println("rla-worker " + VERSION);
+            return;
+        }
+        InputStream in = new BufferedInputStream(new FileInputStream(FileDescriptor.in));
+        OutputStream out = new BufferedOutputStream(new FileOutputStream(FileDescriptor.out));
+        trapExit();
+        writeString(out, "ready");
+        out.flush();
+
+        while (true) {
+            String count = readString(in);
+            if (count == null) {
+                return;
+            }
+            String[] request = new String[Integer.parseInt(count)];
+            for (int i = 0; i < request.length; i++) {
+                request[i] = readString(in);
+                if (request[i] == null) {
+                    return;
+                }
+            }
+            ByteArrayOutputStream stdout = new ByteArrayOutputStream();
+            ByteArrayOutputStream stderr = new ByteArrayOutputStream();
+            int status = run(request[0], Arrays.copyOfRange(request, 1, request.length), stdout, stderr);
+            writeString(out, Integer.toString(status));
+            writeBytes(out, stdout.toByteArray());
+            writeBytes(out, stderr.toByteArray());
+            out.flush();
+        }
+    }
+
+    static void trapExit() {
+        System.setSecurityManager(new SecurityManager() {
+            @Override
+            public void checkPermission(Permission perm) {
+            }
+
+            @Override
+            public void checkPermission(Permission perm, Object context) {
+            }
+
+            @Override
+            public void checkExit(int status) {
+                if (firstExit == null) {
+                    firstExit = status;
+                }
+                throw new Exit(status);
+            }
+        });
+    }
+
+    static int run(String jar, String[] args, ByteArrayOutputStream stdout, ByteArrayOutputStream stderr) {
+        PrintStream oldOut = System.out;
+        PrintStream oldErr = System.err;
+        System.setOut(new PrintStream(stdout, true));
+        System.setErr(new PrintStream(stderr, true));
+        firstExit = null;
+        try {
+            Method main = mainOf(jar);
+            Thread.currentThread().setContextClassLoader(main.getDeclaringClass().getClassLoader());
+            main.invoke(null, (Object) args);
+            return firstExit == null ? 0 : firstExit;
+        } catch (Throwable e) {
+            Throwable cause = e instanceof InvocationTargetException ? e.getCause() : e;
+            if (firstExit != null) {
+                return firstExit;
+            }
+            cause.printStackTrace();
+            return 1;
+        } finally {
+            System.out.flush();
+            System.err.flush();
+            System.setOut(oldOut);
+            System.setErr(oldErr);
+        }
+    }
+
+    static Method mainOf(String jar) throws Exception {
+        Method main = MAINS.get(jar);
+        if (main != null) {
+            return main;
+        }
+        String mainClass;
+        try (JarFile file = new JarFile(jar)) {
+            mainClass = file.getManifest().getMainAttributes().getValue("Main-Class");
+        }
+        if (mainClass == null) {
+            throw new IllegalArgumentException("no Main-Class in " + jar);
+        }
+        // jars don't see classes of each other
+        ClassLoader loader = new URLClassLoader(new URL[] { new File(jar).toURI().toURL() },
+                ClassLoader.getSystemClassLoader().getParent());
+        main = Class.forName(mainClass, true, loader).getMethod("main", String[].class);
+        MAINS.put(jar, main);
+        return main;
+    }
+
+    static String readString(InputStream in) throws IOException {
+        ByteArrayOutputStream buf = new ByteArrayOutputStream();
+        int b;
+        while ((b = in.read()) != 0) {
+            if (b < 0) {
+                return null;
+            }
+            buf.write(b);
+        }
+        return new String(buf.toByteArray(), StandardCharsets.UTF_8);
+    }
+
+    static void writeString(OutputStream out, String s) throws IOException {
+        out.write(s.getBytes(StandardCharsets.UTF_8));
+        out.write(0);
+    }
+
+    static void writeBytes(OutputStream out, byte[] bytes) throws IOException {
+        writeString(out, Integer.toString(bytes.length));
+        out.write(bytes);
+    }
+}
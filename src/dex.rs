@@ -0,0 +1,366 @@
+//! minimal dex parser, classes with their methods are parsed.
+//! method bodies are hashed with references(strings, types, fields, methods) resolved,
+//! so the hash doesn't change when only the index tables of dex are changed
+
+use std::collections::BTreeMap;
+
+use anyhow::{format_err, Context, Result};
+use sha2::{Digest, Sha256};
+
+const PACKED_SWITCH_PAYLOAD: u16 = 0x0100;
+const SPARSE_SWITCH_PAYLOAD: u16 = 0x0200;
+const FILL_ARRAY_DATA_PAYLOAD: u16 = 0x0300;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Index {
+    None,
+    String,
+    Type,
+    Field,
+    Method,
+    Proto,
+    /// call site, method handle
+    Other,
+}
+
+/// (size in code units, index kind, the index is 32 bits)
+fn opcode(op: u8) -> (usize, Index, bool) {
+    use Index::*;
+    match op {
+        0x02 | 0x05 | 0x08 => (2, None, false),
+        0x03 | 0x06 | 0x09 => (3, None, false),
+        0x13 | 0x15 | 0x16 | 0x19 => (2, None, false),
+        0x14 | 0x17 => (3, None, false),
+        0x18 => (5, None, false),
+        0x1a => (2, String, false),
+        0x1b => (3, String, true),
+        0x1c | 0x1f | 0x20 | 0x22 | 0x23 => (2, Type, false),
+        0x24 | 0x25 => (3, Type, false),
+        0x26 | 0x2a | 0x2b | 0x2c => (3, None, false),
+        0x29 => (2, None, false),
+        0x2d..=0x3d => (2, None, false),
+        0x44..=0x51 => (2, None, false),
+        0x52..=0x6d => (2, Field, false),
+        0x6e..=0x72 | 0x74..=0x78 => (3, Method, false),
+        0x90..=0xaf => (2, None, false),
+        0xd0..=0xe2 => (2, None, false),
+        0xfa | 0xfb => (4, Method, false),
+        0xfc | 0xfd => (3, Other, false),
+        0xfe => (2, Other, false),
+        0xff => (2, Proto, false),
+        _ => (1, None, false),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Method {
+    /// e.g `onCreate(Landroid/os/Bundle;)V`
+    pub signature: String,
+    /// hash of the method body, empty for abstract and native methods
+    pub hash: String,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Class {
+    /// descriptor, e.g `Lcom/abc/Main;`
+    pub name: String,
+    pub methods: Vec<Method>,
+}
+
+struct Dex<'a> {
+    data: &'a [u8],
+    strings: Vec<String>,
+    types: Vec<u32>,
+    /// (return type, parameters offset)
+    protos: Vec<(u32, u32)>,
+    /// (class, type, name)
+    fields: Vec<(u16, u16, u32)>,
+    /// (class, proto, name)
+    methods: Vec<(u16, u16, u32)>,
+}
+
+/// the magic is followed by a version, e.g `dex\n035\0`
+pub(crate) fn is_dex_magic(bytes: &[u8]) -> bool {
+    bytes.len() >= 8 && bytes.starts_with(b"dex\n") && bytes[7] == 0
+}
+
+fn uleb128(data: &[u8], off: &mut usize) -> Result<u32> {
+    let mut result = 0u32;
+    for i in 0..5 {
+        let b = *data.get(*off).context("unexpected eof of uleb128")?;
+        *off += 1;
+        result |= ((b & 0x7f) as u32) << (i * 7);
+        if b & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+impl<'a> Dex<'a> {
+    fn u16(&self, off: usize) -> Result<u16> {
+        let b = self
+            .data
+            .get(off..off + 2)
+            .with_context(|| format!("unexpected eof at {off}"))?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&self, off: usize) -> Result<u32> {
+        let b = self
+            .data
+            .get(off..off + 4)
+            .with_context(|| format!("unexpected eof at {off}"))?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// (size, offset) of a section in header
+    fn section(&self, header_off: usize) -> Result<(usize, usize)> {
+        Ok((
+            self.u32(header_off)? as usize,
+            self.u32(header_off + 4)? as usize,
+        ))
+    }
+
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        if !is_dex_magic(data) {
+            return Err(format_err!("not a dex"));
+        }
+        let mut dex = Dex {
+            data,
+            strings: vec![],
+            types: vec![],
+            protos: vec![],
+            fields: vec![],
+            methods: vec![],
+        };
+
+        let (size, off) = dex.section(0x38)?;
+        for i in 0..size {
+            let mut p = dex.u32(off + i * 4)? as usize;
+            uleb128(data, &mut p)?;
+            let end = data
+                .get(p..)
+                .and_then(|d| d.iter().position(|b| *b == 0))
+                .map(|e| p + e)
+                .context("string data not terminated")?;
+            dex.strings
+                .push(String::from_utf8_lossy(&data[p..end]).to_string());
+        }
+        let (size, off) = dex.section(0x40)?;
+        for i in 0..size {
+            dex.types.push(dex.u32(off + i * 4)?);
+        }
+        let (size, off) = dex.section(0x48)?;
+        for i in 0..size {
+            let item = off + i * 12;
+            dex.protos.push((dex.u32(item + 4)?, dex.u32(item + 8)?));
+        }
+        let (size, off) = dex.section(0x50)?;
+        for i in 0..size {
+            let item = off + i * 8;
+            dex.fields
+                .push((dex.u16(item)?, dex.u16(item + 2)?, dex.u32(item + 4)?));
+        }
+        let (size, off) = dex.section(0x58)?;
+        for i in 0..size {
+            let item = off + i * 8;
+            dex.methods
+                .push((dex.u16(item)?, dex.u16(item + 2)?, dex.u32(item + 4)?));
+        }
+        Ok(dex)
+    }
+
+    fn string(&self, idx: u32) -> &str {
+        self.strings
+            .get(idx as usize)
+            .map(String::as_str)
+            .unwrap_or("?")
+    }
+
+    fn type_name(&self, idx: u32) -> &str {
+        self.types
+            .get(idx as usize)
+            .map(|s| self.string(*s))
+            .unwrap_or("?")
+    }
+
+    fn proto(&self, idx: u32) -> String {
+        let (ret, params_off) = match self.protos.get(idx as usize) {
+            Some(p) => *p,
+            None => return "?".to_string(),
+        };
+        let mut params = String::new();
+        if params_off != 0 {
+            let off = params_off as usize;
+            let size = self.u32(off).unwrap_or_default() as usize;
+            for i in 0..size {
+                let t = self.u16(off + 4 + i * 2).unwrap_or(u16::MAX);
+                params.push_str(self.type_name(t as u32));
+            }
+        }
+        format!("({params}){}", self.type_name(ret))
+    }
+
+    /// name with proto, without class
+    fn method_signature(&self, idx: u32) -> String {
+        match self.methods.get(idx as usize) {
+            Some((_, proto, name)) => {
+                format!("{}{}", self.string(*name), self.proto(*proto as u32))
+            }
+            None => "?".to_string(),
+        }
+    }
+
+    fn resolve(&self, kind: Index, idx: u32) -> String {
+        match kind {
+            Index::String => self.string(idx).to_string(),
+            Index::Type => self.type_name(idx).to_string(),
+            Index::Field => match self.fields.get(idx as usize) {
+                Some((class, ty, name)) => format!(
+                    "{}->{}:{}",
+                    self.type_name(*class as u32),
+                    self.string(*name),
+                    self.type_name(*ty as u32)
+                ),
+                None => "?".to_string(),
+            },
+            Index::Method => match self.methods.get(idx as usize) {
+                Some((class, _, _)) => format!(
+                    "{}->{}",
+                    self.type_name(*class as u32),
+                    self.method_signature(idx)
+                ),
+                None => "?".to_string(),
+            },
+            Index::Proto => self.proto(idx),
+            Index::Other | Index::None => idx.to_string(),
+        }
+    }
+
+    /// hash of code item, references are resolved
+    fn code_hash(&self, code_off: usize) -> Result<String> {
+        let registers = self.u16(code_off)?;
+        let insns_size = self.u32(code_off + 12)? as usize;
+        let insns = code_off + 16;
+        let unit = |i: usize| self.u16(insns + i * 2);
+
+        let mut hasher = Sha256::new();
+        hasher.update(registers.to_le_bytes());
+        let mut i = 0;
+        while i < insns_size {
+            let first = unit(i)?;
+            let size = match first {
+                PACKED_SWITCH_PAYLOAD => 4 + unit(i + 1)? as usize * 2,
+                SPARSE_SWITCH_PAYLOAD => 2 + unit(i + 1)? as usize * 4,
+                FILL_ARRAY_DATA_PAYLOAD => {
+                    let width = unit(i + 1)? as usize;
+                    let count = self.u32(insns + (i + 2) * 2)? as usize;
+                    4 + width * count / 2 + width * count % 2
+                }
+                _ => {
+                    let (size, kind, wide) = opcode((first & 0xff) as u8);
+                    if kind != Index::None {
+                        let idx = if wide {
+                            unit(i + 1)? as u32 | (unit(i + 2)? as u32) << 16
+                        } else {
+                            unit(i + 1)? as u32
+                        };
+                        hasher.update(first.to_le_bytes());
+                        hasher.update(self.resolve(kind, idx).as_bytes());
+                        // registers after the index
+                        let rest = if wide { 3 } else { 2 };
+                        for j in rest..size {
+                            hasher.update(unit(i + j)?.to_le_bytes());
+                        }
+                        // invoke-polymorphic has a proto at the last unit
+                        if size == 4 && kind == Index::Method {
+                            hasher.update(self.proto(unit(i + 3)? as u32).as_bytes());
+                        }
+                        i += size;
+                        continue;
+                    }
+                    size
+                }
+            };
+            for j in 0..size.min(insns_size - i) {
+                hasher.update(unit(i + j)?.to_le_bytes());
+            }
+            i += size;
+        }
+        Ok(crate::hash::to_hex(&hasher.finalize()))
+    }
+
+    fn class(&self, def: usize) -> Result<Class> {
+        let name = self.type_name(self.u32(def)?).to_string();
+        let data_off = self.u32(def + 24)? as usize;
+        let mut methods = vec![];
+        if data_off == 0 {
+            return Ok(Class { name, methods });
+        }
+
+        let mut p = data_off;
+        let static_fields = uleb128(self.data, &mut p)?;
+        let instance_fields = uleb128(self.data, &mut p)?;
+        let direct_methods = uleb128(self.data, &mut p)?;
+        let virtual_methods = uleb128(self.data, &mut p)?;
+        for _ in 0..(static_fields + instance_fields) * 2 {
+            uleb128(self.data, &mut p)?;
+        }
+        for count in [direct_methods, virtual_methods] {
+            let mut idx = 0u32;
+            for _ in 0..count {
+                idx = idx.wrapping_add(uleb128(self.data, &mut p)?);
+                let _access = uleb128(self.data, &mut p)?;
+                let code_off = uleb128(self.data, &mut p)? as usize;
+                let hash = if code_off == 0 {
+                    String::new()
+                } else {
+                    self.code_hash(code_off)
+                        .with_context(|| format!("{name} code item error"))?
+                };
+                methods.push(Method {
+                    signature: self.method_signature(idx),
+                    hash,
+                });
+            }
+        }
+        Ok(Class { name, methods })
+    }
+}
+
+/// classes defined in dex, keyed by descriptor
+pub(crate) fn classes(data: &[u8]) -> Result<BTreeMap<String, Class>> {
+    let dex = Dex::parse(data)?;
+    let (size, off) = dex.section(0x60)?;
+    let mut classes = BTreeMap::new();
+    for i in 0..size {
+        let class = dex.class(off + i * 32)?;
+        classes.insert(class.name.clone(), class);
+    }
+    Ok(classes)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_uleb128() {
+        let data = [0x7f, 0x80, 0x7f, 0xe5, 0x8e, 0x26];
+        let mut off = 0;
+        assert_eq!(super::uleb128(&data, &mut off).unwrap(), 0x7f);
+        assert_eq!(super::uleb128(&data, &mut off).unwrap(), 0x3f80);
+        assert_eq!(super::uleb128(&data, &mut off).unwrap(), 624485);
+        assert_eq!(off, data.len());
+    }
+
+    #[test]
+    fn test_opcode() {
+        use super::{opcode, Index};
+        // invoke-virtual
+        assert_eq!(opcode(0x6e), (3, Index::Method, false));
+        // const-string/jumbo
+        assert_eq!(opcode(0x1b), (3, Index::String, true));
+        // return-void
+        assert_eq!(opcode(0x0e), (1, Index::None, false));
+    }
+}
@@ -1,15 +1,16 @@
 use std::{
     ffi::{OsStr, OsString},
-    path::Path,
+    path::{Path, PathBuf},
     sync::atomic::{AtomicUsize, Ordering},
 };
 
+use anyhow::{Context, Result};
 use once_cell::sync::Lazy;
-use tempfile::{tempdir, TempDir, TempPath};
+use tempfile::{TempDir, TempPath};
 
 static BINARIES: Lazy<TempDir> = Lazy::new(|| {
     // assume that it will not create fail
-    match tempdir() {
+    match tempfile::tempdir() {
         Ok(d) => d,
         Err(e) => {
             eprintln!("tempdir create error {e}");
@@ -22,6 +23,17 @@ pub(crate) fn binarydir() -> &'static Path {
     BINARIES.path()
 }
 
+/// persistent cache of rla, `$XDG_CACHE_HOME/rla` or `~/.cache/rla`
+pub(crate) fn cachedir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(base.join("rla"))
+}
+
+/// a unique path in `binarydir`, the file is removed when dropped.
+/// use `tempdir` for a dir, `TempPath` doesn't remove dirs
 pub(crate) fn temppath<T: AsRef<OsStr>>(name: T) -> TempPath {
     static GEN: AtomicUsize = AtomicUsize::new(0);
     let id = GEN.fetch_add(1, Ordering::Relaxed);
@@ -30,6 +42,14 @@ pub(crate) fn temppath<T: AsRef<OsStr>>(name: T) -> TempPath {
     base.push(format!("{id}_"));
     base.push(name.as_ref());
 
-    let path = binarydir().join(name.as_ref());
+    let path = binarydir().join(base);
     TempPath::from_path(path)
 }
+
+/// a new dir in `binarydir`, it's removed with its files when dropped
+pub(crate) fn tempdir(name: &str) -> Result<TempDir> {
+    tempfile::Builder::new()
+        .prefix(&format!("{name}_"))
+        .tempdir_in(binarydir())
+        .with_context(|| format!("temp dir {name} create error"))
+}
@@ -1,5 +1,15 @@
 use chrono::{DateTime, Local};
-use tracing_subscriber::fmt::{format::FmtSpan, time::FormatTime};
+use tracing_subscriber::{
+    field::MakeExt,
+    fmt::{
+        format::{debug_fn, FmtSpan},
+        time::FormatTime,
+    },
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+};
+
+use crate::progress::JsonLayer;
 
 struct Timer;
 
@@ -10,8 +20,12 @@ impl FormatTime for Timer {
     }
 }
 
-/// debug+(verbose), error+(non-verbose)
-pub(crate) fn init_logger(verbose: bool) {
+/// debug+(verbose), info+(non-verbose), info+ as json events for `--message-format json`
+pub(crate) fn init_logger(verbose: bool, json: bool) {
+    if json {
+        tracing_subscriber::registry().with(JsonLayer).init();
+        return;
+    }
     let level = {
         if verbose {
             tracing::Level::DEBUG
@@ -26,7 +40,17 @@ pub(crate) fn init_logger(verbose: bool) {
         .with_target(false)
         .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
         .with_max_level(level)
-        .with_timer(Timer);
+        .with_timer(Timer)
+        // `path` of a log is for json events, it's in the message already
+        .fmt_fields(
+            debug_fn(|w, field, value| match field.name() {
+                "message" => write!(w, "{value:?}"),
+                "path" => Ok(()),
+                name => write!(w, "{name}={value:?}"),
+            })
+            .delimited(" "),
+        )
+        .with_writer(crate::progress::writer);
     if verbose {
         builder.init();
     } else {
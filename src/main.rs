@@ -1,18 +1,23 @@
 #![feature(exit_status_error)]
 
+mod axml;
+mod cert;
 mod cli;
 mod cmd;
 mod core;
 mod deps;
+mod dex;
 mod dir;
+mod hash;
 mod log;
+mod progress;
 mod runtime;
 mod zip;
 
 fn main() {
     let start = std::time::Instant::now();
     if let Err(e) = cli::run() {
-        tracing::error!("{e:?}");
+        progress::error(&e);
         std::process::exit(1);
     } else {
         tracing::debug!("task DONE:  {:.2?}", start.elapsed());
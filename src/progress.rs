@@ -0,0 +1,505 @@
+//! progress of long operations, e.g baksmali of each dex, jadx and git commit.
+//! running phases are shown in a status line on a tty and finished ones are logged,
+//! `--message-format json` emits json events to stdout instead
+
+use std::{
+    fmt::Debug,
+    io::{self, Write},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{format_err, Result};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tracing::{
+    field::{Field, Visit},
+    info, Event as TracingEvent, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+use crate::cmd::{Diagnostic, Tool, ToolError};
+
+/// the status line is redrawn in this interval for elapsed times
+const TICK: Duration = Duration::from_millis(200);
+/// the status line is cut to fit in most terminals
+const STATUS_WIDTH: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for MessageFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(format_err!("unknown message format {s:?}, human or json")),
+        }
+    }
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static IDS: AtomicUsize = AtomicUsize::new(0);
+static STATUS: Lazy<Mutex<Status>> = Lazy::new(Default::default);
+
+#[cfg(unix)]
+fn stderr_is_tty() -> bool {
+    extern "C" {
+        fn isatty(fd: i32) -> i32;
+    }
+    // SAFETY: isatty only reads the fd
+    unsafe { isatty(2) == 1 }
+}
+
+#[cfg(not(unix))]
+fn stderr_is_tty() -> bool {
+    false
+}
+
+static TTY: Lazy<bool> = Lazy::new(stderr_is_tty);
+
+pub fn set_format(format: MessageFormat) {
+    JSON.store(format == MessageFormat::Json, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// the status line is shown on a tty only
+fn show_status() -> bool {
+    *TTY && !is_json()
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+enum Event<'a> {
+    PhaseStart {
+        phase: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<&'a str>,
+    },
+    PhaseEnd {
+        phase: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<&'a str>,
+        elapsed_ms: u128,
+        ok: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    Message {
+        message: &'a str,
+    },
+    Warning {
+        message: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<&'a str>,
+    },
+    Error {
+        message: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<&'a str>,
+    },
+    /// parsed from the output of a tool, e.g a syntax error of smali
+    Diagnostic {
+        tool: Tool,
+        #[serde(flatten)]
+        diagnostic: &'a Diagnostic,
+    },
+    /// output of a command asked by its `--json`, e.g `rla info --json`
+    Result {
+        result: serde_json::Value,
+    },
+}
+
+/// diagnostics of a failed tool in the error, once for an error
+fn emit_diagnostics(e: &anyhow::Error) -> Option<&ToolError> {
+    let tool_error = e.chain().find_map(|e| e.downcast_ref::<ToolError>())?;
+    if tool_error.report() {
+        for diagnostic in &tool_error.diagnostics {
+            emit(&Event::Diagnostic {
+                tool: tool_error.tool,
+                diagnostic,
+            });
+        }
+    }
+    Some(tool_error)
+}
+
+fn emit(event: &Event<'_>) {
+    if let Ok(line) = serde_json::to_string(event) {
+        let mut out = io::stdout().lock();
+        writeln!(out, "{line}").and_then(|_| out.flush()).ok();
+    }
+}
+
+/// `println!` for reports of commands, e.g the table of `rla doctor`.
+/// they go to stderr in json mode, so stdout has json events only
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        if $crate::progress::is_json() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+pub(crate) use outln;
+
+/// output of `--json`, pretty printed, or a `result` event in json mode
+pub fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    if is_json() {
+        emit(&Event::Result {
+            result: serde_json::to_value(value)?,
+        });
+    } else {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    }
+    Ok(())
+}
+
+struct Running {
+    id: usize,
+    phase: &'static str,
+    group: Option<usize>,
+    start: Instant,
+}
+
+struct Group {
+    id: usize,
+    phase: &'static str,
+    total: usize,
+    done: usize,
+}
+
+#[derive(Default)]
+struct Status {
+    running: Vec<Running>,
+    groups: Vec<Group>,
+    /// a status line is on the screen
+    shown: bool,
+    ticking: bool,
+}
+
+impl Status {
+    fn line(&self) -> String {
+        let mut items = self
+            .groups
+            .iter()
+            .filter(|g| self.running.iter().any(|r| r.group == Some(g.id)))
+            .map(|g| format!("{} {}/{}", g.phase, g.done, g.total))
+            .collect::<Vec<_>>();
+        items.extend(
+            self.running
+                .iter()
+                .filter(|r| r.group.is_none())
+                .map(|r| format!("{} {}s", r.phase, r.start.elapsed().as_secs())),
+        );
+        let mut line = format!("running: {}", items.join(", "));
+        if line.len() > STATUS_WIDTH {
+            line.truncate(STATUS_WIDTH - 3);
+            line.push_str("...");
+        }
+        line
+    }
+
+    fn clear(&mut self, err: &mut impl Write) {
+        if self.shown {
+            write!(err, "\r\x1b[K").ok();
+            self.shown = false;
+        }
+    }
+
+    fn draw(&mut self, err: &mut impl Write) {
+        self.clear(err);
+        if !self.running.is_empty() {
+            write!(err, "{}", self.line()).ok();
+            self.shown = true;
+        }
+        err.flush().ok();
+    }
+}
+
+/// redraw the status line for elapsed times, until no phase is running
+fn tick() {
+    loop {
+        thread::sleep(TICK);
+        let mut status = match STATUS.lock() {
+            Ok(status) => status,
+            Err(_) => return,
+        };
+        if status.running.is_empty() {
+            status.ticking = false;
+            return;
+        }
+        status.draw(&mut io::stderr());
+    }
+}
+
+/// a running phase, it's ended by `end`, or removed when dropped
+pub struct Phase {
+    id: usize,
+    phase: &'static str,
+    name: Option<String>,
+    group: Option<usize>,
+    start: Instant,
+}
+
+pub fn start(phase: &'static str, name: Option<String>) -> Phase {
+    start_in(phase, name, None)
+}
+
+fn start_in(phase: &'static str, name: Option<String>, group: Option<usize>) -> Phase {
+    let id = IDS.fetch_add(1, Ordering::Relaxed);
+    let start = Instant::now();
+    if is_json() {
+        emit(&Event::PhaseStart {
+            phase,
+            name: name.as_deref(),
+        });
+    } else if show_status() {
+        if let Ok(mut status) = STATUS.lock() {
+            status.running.push(Running {
+                id,
+                phase,
+                group,
+                start,
+            });
+            status.draw(&mut io::stderr());
+            if !status.ticking {
+                status.ticking = true;
+                thread::spawn(tick);
+            }
+        }
+    }
+    Phase {
+        id,
+        phase,
+        name,
+        group,
+        start,
+    }
+}
+
+impl Phase {
+    pub fn end<T>(self, result: &Result<T>) -> Duration {
+        let elapsed = self.start.elapsed();
+        let done = self.group.and_then(|g| {
+            let mut status = STATUS.lock().ok()?;
+            let group = status.groups.iter_mut().find(|group| group.id == g)?;
+            group.done += 1;
+            Some((group.done, group.total))
+        });
+        if is_json() {
+            if let Err(e) = result {
+                emit_diagnostics(e);
+            }
+            emit(&Event::PhaseEnd {
+                phase: self.phase,
+                name: self.name.as_deref(),
+                elapsed_ms: elapsed.as_millis(),
+                ok: result.is_ok(),
+                error: result.as_ref().err().map(|e| format!("{e:#}")),
+            });
+            return elapsed;
+        }
+        let state = if result.is_ok() { "done" } else { "failed" };
+        let name = self
+            .name
+            .as_deref()
+            .map(|n| format!(" {n}"))
+            .unwrap_or_default();
+        let secs = elapsed.as_secs_f32();
+        match done {
+            // jobs of a group are always logged, a single job is not worth it
+            Some((done, total)) if total > 1 => {
+                info!("[{done}/{total}] {}{name} {state}, {secs:.1}s", self.phase)
+            }
+            _ if show_status() => info!("{}{name} {state}, {secs:.1}s", self.phase),
+            _ => {}
+        }
+        elapsed
+    }
+}
+
+impl Drop for Phase {
+    fn drop(&mut self) {
+        if !show_status() {
+            return;
+        }
+        if let Ok(mut status) = STATUS.lock() {
+            status.running.retain(|r| r.id != self.id);
+            status.draw(&mut io::stderr());
+        }
+    }
+}
+
+/// jobs of a phase, e.g baksmali of each dex, shown as `baksmali 3/40`
+#[derive(Clone)]
+pub struct Jobs(Arc<JobsInner>);
+
+struct JobsInner {
+    id: usize,
+    phase: &'static str,
+}
+
+pub fn jobs(phase: &'static str, total: usize) -> Jobs {
+    let id = IDS.fetch_add(1, Ordering::Relaxed);
+    if let Ok(mut status) = STATUS.lock() {
+        status.groups.push(Group {
+            id,
+            phase,
+            total,
+            done: 0,
+        });
+    }
+    Jobs(Arc::new(JobsInner { id, phase }))
+}
+
+impl Jobs {
+    pub fn start(&self, name: String) -> Phase {
+        start_in(self.0.phase, Some(name), Some(self.0.id))
+    }
+}
+
+impl Drop for JobsInner {
+    fn drop(&mut self) {
+        if let Ok(mut status) = STATUS.lock() {
+            status.groups.retain(|g| g.id != self.id);
+        }
+    }
+}
+
+/// the final error of a command
+pub fn error(e: &anyhow::Error) {
+    if is_json() {
+        let path = emit_diagnostics(e)
+            .and_then(|t| t.diagnostics.iter().find_map(|d| d.file.as_deref()))
+            .map(|p| p.to_string_lossy());
+        emit(&Event::Error {
+            message: &format!("{e:#}"),
+            path: path.as_deref(),
+        });
+    } else {
+        tracing::error!("{e:?}");
+    }
+}
+
+/// log writer, which keeps the status line below logs
+pub struct Writer;
+
+pub fn writer() -> Writer {
+    Writer
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut status = STATUS.lock().map_err(|_| io::ErrorKind::Other)?;
+        let mut err = io::stderr();
+        status.clear(&mut err);
+        let mut out = io::stdout().lock();
+        out.write_all(buf)?;
+        out.flush()?;
+        if show_status() {
+            status.draw(&mut err);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    path: Option<String>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            "path" => self.path = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        match field.name() {
+            "message" => self.message = format!("{value:?}"),
+            "path" => self.path = Some(format!("{value:?}").trim_matches('"').to_string()),
+            _ => {}
+        }
+    }
+}
+
+/// logs of info and above as json events, a `path` field of the log is kept
+pub struct JsonLayer;
+
+impl<S: Subscriber> Layer<S> for JsonLayer {
+    fn on_event(&self, event: &TracingEvent<'_>, _ctx: Context<'_, S>) {
+        let level = *event.metadata().level();
+        if level > Level::INFO {
+            return;
+        }
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        // `{e:?}` of anyhow errors may have a backtrace, which is not for machines
+        let message = match fields.message.find("\n\nStack backtrace:") {
+            Some(i) => fields.message[..i].trim_end(),
+            None => fields.message.trim_end(),
+        };
+        let path = fields.path.as_deref();
+        match level {
+            Level::ERROR => emit(&Event::Error { message, path }),
+            Level::WARN => emit(&Event::Warning { message, path }),
+            _ => emit(&Event::Message { message }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_format() {
+        assert_eq!(
+            "json".parse::<MessageFormat>().unwrap(),
+            MessageFormat::Json
+        );
+        assert_eq!(
+            "human".parse::<MessageFormat>().unwrap(),
+            MessageFormat::Human
+        );
+        assert!("xml".parse::<MessageFormat>().is_err());
+    }
+
+    #[test]
+    fn test_event() {
+        let end = Event::PhaseEnd {
+            phase: "smali",
+            name: Some("classes.dex"),
+            elapsed_ms: 12,
+            ok: true,
+            error: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&end).unwrap(),
+            r#"{"event":"phase-end","phase":"smali","name":"classes.dex","elapsed_ms":12,"ok":true}"#
+        );
+    }
+}
@@ -1,5 +1,34 @@
+use std::{
+    fs,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
 use once_cell::sync::Lazy;
-use tokio::runtime::Runtime;
+use tokio::{runtime::Runtime, sync::Semaphore};
+use tracing::{debug, warn};
+
+use crate::progress;
+
+/// memory taken by a job, which is mostly a jvm running smali or baksmali
+const JOB_MEMORY: u64 = 512 * 1024 * 1024;
+
+/// a job waiting for memory checks it again in this interval
+const MEMORY_POLL: Duration = Duration::from_millis(200);
+
+/// max of jobs at once, 0 is the default
+static JOBS: AtomicUsize = AtomicUsize::new(0);
+
+/// slots of `Jobs`, single phases(e.g jadx, git) are not limited by them
+static SLOTS: Lazy<Semaphore> = Lazy::new(|| {
+    let jobs = jobs();
+    debug!("run at most {jobs} jobs at once");
+    Semaphore::new(jobs)
+});
+
+/// jobs running their tools
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
 
 static RUNTIME: Lazy<Runtime> =
     Lazy::new(|| tokio::runtime::Builder::new_multi_thread().build().unwrap());
@@ -7,3 +36,121 @@ static RUNTIME: Lazy<Runtime> =
 pub fn rt() -> &'static Runtime {
     &*RUNTIME
 }
+
+/// `MemAvailable` of `/proc/meminfo`
+fn available_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemAvailable:"))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kb * 1024)
+}
+
+/// jobs of memory available, `None` if memory is unknown
+fn memory_jobs() -> Option<usize> {
+    available_memory().map(|m| ((m / JOB_MEMORY) as usize).max(1))
+}
+
+/// cpu count, but no more than the memory allows
+fn default_jobs() -> usize {
+    let cpus = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    memory_jobs().map_or(cpus, |m| m.min(cpus))
+}
+
+/// set by `-j/--jobs` before the runtime is used
+pub fn set_jobs(jobs: Option<usize>) {
+    if let Some(jobs) = jobs {
+        if let Some(m) = memory_jobs().filter(|m| jobs > *m) {
+            warn!("{jobs} jobs may run out of memory, which is enough for about {m} jobs");
+        }
+        JOBS.store(jobs.max(1), Ordering::Relaxed);
+    }
+}
+
+pub fn jobs() -> usize {
+    match JOBS.load(Ordering::Relaxed) {
+        0 => default_jobs(),
+        jobs => jobs,
+    }
+}
+
+/// run a blocking call(e.g an external tool) on the blocking pool, instead of async worker threads
+pub async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .context("blocking job panicked")?
+}
+
+/// a blocking call shown as a phase of progress, e.g jadx
+pub async fn phase<T, F>(phase: &'static str, name: Option<String>, f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    blocking(move || {
+        // started on the pool, waiting for a free slot is not counted
+        let progress = progress::start(phase, name);
+        let result = f();
+        progress.end(&result);
+        result
+    })
+    .await
+}
+
+/// a running job, counted until it's dropped
+struct Running;
+
+impl Running {
+    /// wait until there is memory for another job, the first job never waits
+    fn start() -> Self {
+        while RUNNING.load(Ordering::Relaxed) > 0
+            && matches!(available_memory(), Some(m) if m < JOB_MEMORY)
+        {
+            std::thread::sleep(MEMORY_POLL);
+        }
+        RUNNING.fetch_add(1, Ordering::Relaxed);
+        Running
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// a group of blocking jobs, e.g baksmali of each dex, each job reports when it's done.
+/// jobs of all groups share `--jobs` slots
+pub struct Jobs {
+    progress: progress::Jobs,
+}
+
+impl Jobs {
+    pub fn new(kind: &'static str, total: usize) -> Self {
+        Self {
+            progress: progress::jobs(kind, total),
+        }
+    }
+
+    pub async fn run<T, F>(&self, name: String, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let jobs = self.progress.clone();
+        let _slot = SLOTS.acquire().await.context("job slots closed")?;
+        blocking(move || {
+            let _running = Running::start();
+            let progress = jobs.start(name);
+            let result = f();
+            progress.end(&result);
+            result
+        })
+        .await
+    }
+}
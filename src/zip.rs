@@ -1,5 +1,6 @@
 use anyhow::{Context, Result};
 use std::{
+    collections::BTreeMap,
     fs, io,
     io::{BufReader, BufWriter, Read, Write},
     path::Path,
@@ -42,6 +43,56 @@ where
     Ok(())
 }
 
+pub(crate) fn entry_names(apk: &Path) -> Result<Vec<String>> {
+    let reader =
+        BufReader::new(fs::File::open(apk).with_context(|| format!("{apk:?} open error"))?);
+    let archive = zip::ZipArchive::new(reader).with_context(|| format!("{apk:?} is not a zip"))?;
+    Ok(archive.file_names().map(str::to_string).collect())
+}
+
+/// dex files at the root of apk, e.g `classes.dex`, `classes2.dex`
+pub(crate) fn dex_names(apk: &Path) -> Result<Vec<String>> {
+    Ok(entry_names(apk)?
+        .into_iter()
+        .filter(|n| !n.contains('/') && n.ends_with(".dex"))
+        .collect())
+}
+
+/// (size, sha256) of each file entry
+pub(crate) fn entry_hashes(apk: &Path) -> Result<BTreeMap<String, (u64, String)>> {
+    let reader =
+        BufReader::new(fs::File::open(apk).with_context(|| format!("{apk:?} open error"))?);
+    let mut archive =
+        zip::ZipArchive::new(reader).with_context(|| format!("{apk:?} is not a zip"))?;
+    let mut hashes = BTreeMap::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+        let name = file.name().to_string();
+        let size = file.size();
+        let hash = crate::hash::sha256_reader(&mut file)
+            .with_context(|| format!("{name} read error in {apk:?}"))?;
+        hashes.insert(name, (size, hash));
+    }
+    Ok(hashes)
+}
+
+/// read a whole entry into memory
+pub(crate) fn read_entry(apk: &Path, name: &str) -> Result<Vec<u8>> {
+    let reader =
+        BufReader::new(fs::File::open(apk).with_context(|| format!("{apk:?} open error"))?);
+    let mut archive =
+        zip::ZipArchive::new(reader).with_context(|| format!("{apk:?} is not a zip"))?;
+    let mut file = archive
+        .by_name(name)
+        .with_context(|| format!("{name} not found in {apk:?}"))?;
+    let mut bytes = vec![];
+    file.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
 pub(crate) fn zip(src: &Path, dest: &Path) -> Result<()> {
     let mut writer = zip::ZipWriter::new(BufWriter::new(
         fs::File::create(dest).with_context(|| format!("{dest:?} create error"))?,